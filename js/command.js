@@ -51,6 +51,73 @@ function getProvider() {
 const provider = getProvider();
 let program = new anchor.Program(idl, programID, provider);
 console.log(programID.toString(), ANCHOR_PROVIDER_URL)
+const pendingActionAddress = async (id) => {
+    return (await anchor.web3.PublicKey.findProgramAddress(
+        [
+            poolPubkey.toBuffer(),
+            Buffer.from('pending_action'),
+            new anchor.BN(id).toArrayLike(Buffer, 'le', 8)
+        ],
+        program.programId
+    ));
+}
+
+const queuePendingAction = async (method, ...args) => {
+    let poolObject = await program.account.pool.fetch(poolPubkey);
+    const [
+        pendingAction,
+        nonce,
+    ] = await pendingActionAddress(poolObject.nextActionId);
+    await program.rpc[method](nonce, ...args, {
+        accounts: {
+            pool: poolPubkey,
+            pendingAction,
+            authority: provider.wallet.publicKey,
+            systemProgram: anchor.web3.SystemProgram.programId,
+        },
+    });
+    const actionObject = await program.account.pendingAction.fetch(pendingAction);
+    console.log("Queued action: ", poolObject.nextActionId.toString());
+    console.log("Executable after: ", actionObject.eta.toString());
+}
+
+const ACTION_KINDS = {
+    setEmissionSchedule: 3,
+    setCandyMachineRewardPerToken: 4,
+    removeCandyMachineRewardPerToken: 5,
+    setRewardStream: 6,
+    setLoyaltyMultipliers: 7,
+    setTierMultipliers: 8,
+    setSetBonus: 9,
+    setEarlyUnstakePenalty: 10,
+    removeCandyMachine: 11,
+    setRarityRoot: 12,
+};
+
+// Rate changes are timelocked: without `--execute=<ACTION_ID>` the change is queued,
+// with it the queued change is applied. Both runs take the same arguments.
+const runTimelocked = async (method, args, accounts) => {
+    const execute = argv.find(x => x.indexOf('--execute=') == 0);
+    if (!execute) {
+        const ix = idl.instructions.find(x => x.name == method);
+        const data = {};
+        ix.args.forEach((arg, i) => data[arg.name] = args[i]);
+        const encoded = program.coder.instruction.encode(method, data).slice(8);
+        await queuePendingAction('queueAction', ACTION_KINDS[method], encoded);
+        return;
+    }
+
+    const [pendingAction] = await pendingActionAddress(execute.split('=')[1]);
+    const actionObject = await program.account.pendingAction.fetch(pendingAction);
+    await program.rpc[method](...args, {
+        accounts: {
+            ...accounts,
+            pendingAction,
+            proposer: actionObject.proposer,
+        },
+    });
+}
+
 const setRewardPerToken = async () => {
     if (!values[0]) {
         console.log('Missing some arguments.\n\nyarn set_reward_per_token <REWARD_AMOUNT>');
@@ -58,12 +125,128 @@ const setRewardPerToken = async () => {
     }
 
     const rewardPerToken = new anchor.BN(values[0] * anchor.web3.LAMPORTS_PER_SOL);
-    await program.rpc.setRewardPerToken(rewardPerToken, {
+    await queuePendingAction('queueSetRewardPerToken', rewardPerToken);
+}
+
+const executeSetRewardPerToken = async () => {
+    if (!values[0]) {
+        console.log('Missing some arguments.\n\nyarn execute_set_reward_per_token <ACTION_ID>');
+        return;
+    }
+
+    let poolObject = await program.account.pool.fetch(poolPubkey);
+    const [pendingAction] = await pendingActionAddress(values[0]);
+    const actionObject = await program.account.pendingAction.fetch(pendingAction);
+    const [
+        _vaultPubkey,
+        _vaultNonce,
+    ] = await anchor.web3.PublicKey.findProgramAddress(
+        [poolObject.authority.toBuffer(), poolPubkey.toBuffer()],
+        program.programId
+    );
+    const [
+        cmRewardPerToken,
+    ] = await anchor.web3.PublicKey.findProgramAddress(
+        [
+            poolPubkey.toBuffer(),
+            Buffer.from('reward_per_token')
+        ],
+        program.programId
+    );
+    await program.rpc.executeSetRewardPerToken({
         accounts: {
             // Stake instance.
+            pool: poolPubkey,
+            vault: _vaultPubkey,
+            cmRewardPerToken,
+            pendingAction,
+            proposer: actionObject.proposer,
+        },
+    });
+}
+
+const setEmissionSchedule = async () => {
+    if (!values[0] || !values[1] || !values[2] || !values[3]) {
+        console.log('Missing some arguments.\n\nyarn set_emission_schedule <START_TIME> <END_TIME> <STEP_INTERVAL> <STEP_BPS>');
+        return;
+    }
+
+    const [
+        _vaultPubkey,
+        _vaultNonce,
+    ] = await anchor.web3.PublicKey.findProgramAddress(
+        [provider.wallet.publicKey.toBuffer(), poolPubkey.toBuffer()],
+        program.programId
+    );
+    const [
+        cmRewardPerToken,
+    ] = await anchor.web3.PublicKey.findProgramAddress(
+        [
+            poolPubkey.toBuffer(),
+            Buffer.from('reward_per_token')
+        ],
+        program.programId
+    );
+    const [
+        setBonus,
+    ] = await anchor.web3.PublicKey.findProgramAddress(
+        [
+            poolPubkey.toBuffer(),
+            Buffer.from('set_bonus')
+        ],
+        program.programId
+    );
+
+    await runTimelocked('setEmissionSchedule', [
+        new anchor.BN(values[0]),
+        new anchor.BN(values[1]),
+        new anchor.BN(values[2]),
+        Number(values[3]),
+    ], {
+        // Stake instance.
+        pool: poolPubkey,
+        vault: _vaultPubkey,
+        cmRewardPerToken,
+        setBonus,
+        authority: provider.wallet.publicKey,
+    });
+}
+
+const proposeAuthority = async () => {
+    if (!values[0]) {
+        console.log('Missing an arguments.\n\nyarn propose_authority <NEW_AUTHORITY>');
+        return;
+    }
+
+    const newAuthority = new anchor.web3.PublicKey(values[0]);
+    await program.rpc.proposeAuthority(newAuthority, {
+        accounts: {
+            pool: poolPubkey,
+            authority: provider.wallet.publicKey,
+        },
+    });
+}
+
+const acceptAuthority = async () => {
+    await program.rpc.acceptAuthority({
+        accounts: {
+            pool: poolPubkey,
+            pendingAuthority: provider.wallet.publicKey,
+        },
+    });
+}
+
+const setRoles = async () => {
+    if (!values[0] || !values[1] || !values[2] || !values[3]) {
+        console.log('Missing some arguments.\n\nyarn set_roles <RATE_MANAGER> <COLLECTION_MANAGER> <PAUSER> <TREASURER>');
+        return;
+    }
+
+    const [rateManager, collectionManager, pauser, treasurer] = values.slice(0, 4).map(x => new anchor.web3.PublicKey(x));
+    await program.rpc.setRoles(rateManager, collectionManager, pauser, treasurer, {
+        accounts: {
             pool: poolPubkey,
             authority: provider.wallet.publicKey,
-            systemProgram: anchor.web3.SystemProgram.programId,
         },
     });
 }
@@ -88,13 +271,23 @@ const addCandyMachine = async () => {
     let vaultObject = await program.account.vault.fetch(_vaultPubkey);
     console.log(rewardType, candyMachine.toString(), poolPubkey.toString(), provider.wallet.publicKey.toString())
     console.log("Pool authority: ", poolObject.authority.toString())
-    console.log("Pool paused: ", poolObject.paused)
+    console.log("Pool pause flags: ", poolObject.pauseFlags)
     console.log("Candymachines: ", vaultObject.candyMachines)
+    const [
+        cmRewardPerToken,
+    ] = await anchor.web3.PublicKey.findProgramAddress(
+        [
+            poolPubkey.toBuffer(),
+            Buffer.from('reward_per_token')
+        ],
+        program.programId
+    );
     await program.rpc.addCandyMachine(candyMachine, rewardType, {
         accounts: {
             // Stake instance.
             pool: poolPubkey,
             vault: _vaultPubkey,
+            cmRewardPerToken,
             authority: provider.wallet.publicKey,
             systemProgram: anchor.web3.SystemProgram.programId,
         },
@@ -117,14 +310,115 @@ const removeCandyMachine = async () => {
         program.programId
     );
 
-    await program.rpc.removeCandyMachine(candyMachine, {
-        accounts: {
-            // Stake instance.
-            pool: poolPubkey,
-            vault: _vaultPubkey,
-            authority: provider.wallet.publicKey,
-            systemProgram: anchor.web3.SystemProgram.programId,
-        },
+    const [
+        cmRewardPerToken,
+    ] = await anchor.web3.PublicKey.findProgramAddress(
+        [
+            poolPubkey.toBuffer(),
+            Buffer.from('reward_per_token')
+        ],
+        program.programId
+    );
+    await runTimelocked('removeCandyMachine', [candyMachine], {
+        // Stake instance.
+        pool: poolPubkey,
+        vault: _vaultPubkey,
+        cmRewardPerToken,
+        authority: provider.wallet.publicKey,
+    });
+}
+
+const setEarlyUnstakePenalty = async () => {
+    if (!values[0] || !values[1]) {
+        console.log('Missing some arguments.\n\nyarn set_early_unstake_penalty <CANDY_MACHINE_ID> <PENALTY_BPS>');
+        return;
+    }
+
+    const candyMachine = new anchor.web3.PublicKey(values[0]);
+    const penaltyBps = Number(values[1]);
+
+    const [
+        _vaultPubkey,
+        _vaultNonce,
+    ] = await anchor.web3.PublicKey.findProgramAddress(
+        [provider.wallet.publicKey.toBuffer(), poolPubkey.toBuffer()],
+        program.programId
+    );
+
+    await runTimelocked('setEarlyUnstakePenalty', [candyMachine, penaltyBps], {
+        // Stake instance.
+        pool: poolPubkey,
+        vault: _vaultPubkey,
+        authority: provider.wallet.publicKey,
+    });
+}
+
+const setLoyaltyMultipliers = async () => {
+    if (values.length == 0 || values.length % 2 != 0) {
+        console.log('Missing some arguments.\n\nyarn set_loyalty_multipliers <DAYS> <MULTIPLIER_BPS> [<DAYS> <MULTIPLIER_BPS> ...]');
+        return;
+    }
+
+    const days = [];
+    const multiplierBps = [];
+    for (let i = 0; i < values.length; i += 2) {
+        days.push(Number(values[i]));
+        multiplierBps.push(Number(values[i + 1]));
+    }
+
+    const [
+        _vaultPubkey,
+        _vaultNonce,
+    ] = await anchor.web3.PublicKey.findProgramAddress(
+        [provider.wallet.publicKey.toBuffer(), poolPubkey.toBuffer()],
+        program.programId
+    );
+
+    await runTimelocked('setLoyaltyMultipliers', [days, multiplierBps], {
+        // Stake instance.
+        pool: poolPubkey,
+        vault: _vaultPubkey,
+        authority: provider.wallet.publicKey,
+        systemProgram: anchor.web3.SystemProgram.programId,
+    });
+}
+
+const setRarityRoot = async () => {
+    if (!values[0]) {
+        console.log('Missing an arguments.\n\nyarn set_rarity_root <MERKLE_ROOT_HEX>');
+        return;
+    }
+
+    const rarityRoot = Array.from(Buffer.from(values[0].replace(/^0x/, ''), 'hex'));
+    if (rarityRoot.length != 32) {
+        console.log('Merkle root must be 32 bytes');
+        return;
+    }
+
+    await runTimelocked('setRarityRoot', [rarityRoot], {
+        // Stake instance.
+        pool: poolPubkey,
+        authority: provider.wallet.publicKey,
+    });
+}
+
+const setTierMultipliers = async () => {
+    const multiplierBps = values.map(x => Number(x));
+
+    const [
+        _vaultPubkey,
+        _vaultNonce,
+    ] = await anchor.web3.PublicKey.findProgramAddress(
+        [provider.wallet.publicKey.toBuffer(), poolPubkey.toBuffer()],
+        program.programId
+    );
+
+    await runTimelocked('setTierMultipliers', [multiplierBps], {
+        // Stake instance.
+        pool: poolPubkey,
+        vault: _vaultPubkey,
+        authority: provider.wallet.publicKey,
+        systemProgram: anchor.web3.SystemProgram.programId,
     });
 }
 
@@ -139,7 +433,22 @@ const withdrawRewardToken = async () => {
         console.log('Amount must be number');
         return;
     }
+
+    var mintRewardsPublicKey = new anchor.web3.PublicKey(REWARD_TOKEN);
+    var mintRewardsObject = new Token(provider.connection, mintRewardsPublicKey, TOKEN_PROGRAM_ID, provider.wallet.payer);
+    var mintRewardsInfo = await mintRewardsObject.getOrCreateAssociatedAccountInfo(provider.wallet.publicKey);
+    await queuePendingAction('queueWithdrawReward', new anchor.BN(amount * anchor.web3.LAMPORTS_PER_SOL), mintRewardsInfo.address);
+}
+
+const executeWithdrawReward = async () => {
+    if (!values[0]) {
+        console.log('Missing an arguments.\n\nyarn execute_withdraw_reward <ACTION_ID>');
+        return;
+    }
+
     let poolObject = await program.account.pool.fetch(poolPubkey);
+    const [pendingAction] = await pendingActionAddress(values[0]);
+    const actionObject = await program.account.pendingAction.fetch(pendingAction);
 
     const [
         _poolSigner,
@@ -150,16 +459,14 @@ const withdrawRewardToken = async () => {
     );
     let poolSigner = _poolSigner;
 
-    var mintRewardsPublicKey = new anchor.web3.PublicKey(REWARD_TOKEN);
-    var mintRewardsObject = new Token(provider.connection, mintRewardsPublicKey, TOKEN_PROGRAM_ID, provider.wallet.payer);
-    var mintRewardsInfo = await mintRewardsObject.getOrCreateAssociatedAccountInfo(provider.wallet.publicKey);
-    await program.rpc.withdrawReward(new anchor.BN(amount * anchor.web3.LAMPORTS_PER_SOL),
+    await program.rpc.executeWithdrawReward(
         {
             accounts: {
                 rewardVault: poolObject.rewardVault,
-                rewardAccount: mintRewardsInfo.address,
+                rewardAccount: actionObject.rewardAccount,
                 pool: poolPubkey,
-                owner: provider.wallet.publicKey,
+                pendingAction,
+                proposer: actionObject.proposer,
                 poolSigner: poolSigner,
                 tokenProgram: TOKEN_PROGRAM_ID,
             },
@@ -167,6 +474,64 @@ const withdrawRewardToken = async () => {
     );
 }
 
+const setTimelockDelay = async () => {
+    if (!values[0]) {
+        console.log('Missing an arguments.\n\nyarn set_timelock_delay <SECONDS>');
+        return;
+    }
+
+    await program.rpc.setTimelockDelay(new anchor.BN(values[0]), {
+        accounts: {
+            pool: poolPubkey,
+            authority: provider.wallet.publicKey,
+        },
+    });
+}
+
+const queueSetTimelockDelay = async () => {
+    if (!values[0]) {
+        console.log('Missing an arguments.\n\nyarn queue_set_timelock_delay <SECONDS>');
+        return;
+    }
+
+    await queuePendingAction('queueSetTimelockDelay', new anchor.BN(values[0]));
+}
+
+const executeSetTimelockDelay = async () => {
+    if (!values[0]) {
+        console.log('Missing an arguments.\n\nyarn execute_set_timelock_delay <ACTION_ID>');
+        return;
+    }
+
+    const [pendingAction] = await pendingActionAddress(values[0]);
+    const actionObject = await program.account.pendingAction.fetch(pendingAction);
+    await program.rpc.executeSetTimelockDelay({
+        accounts: {
+            pool: poolPubkey,
+            pendingAction,
+            proposer: actionObject.proposer,
+        },
+    });
+}
+
+const cancelPendingAction = async () => {
+    if (!values[0]) {
+        console.log('Missing an arguments.\n\nyarn cancel_pending_action <ACTION_ID>');
+        return;
+    }
+
+    const [pendingAction] = await pendingActionAddress(values[0]);
+    const actionObject = await program.account.pendingAction.fetch(pendingAction);
+    await program.rpc.cancelPendingAction({
+        accounts: {
+            pool: poolPubkey,
+            pendingAction,
+            proposer: actionObject.proposer,
+            authority: provider.wallet.publicKey,
+        },
+    });
+}
+
 const depositRewardToken = async () => {
     if (!values[0]) {
         console.log('Missing an arguments.\n\nyarn deposit_reward_token <AMOUNT>');
@@ -254,25 +619,174 @@ const setCandyMachineRewardPerToken = async () => {
         ],
         program.programId
     );
-    await program.rpc.setCandyMachineRewardPerToken(candyMachine, reward, {
+    const [
+        _vaultPubkey,
+        _vaultNonce,
+    ] = await anchor.web3.PublicKey.findProgramAddress(
+        [provider.wallet.publicKey.toBuffer(), poolPubkey.toBuffer()],
+        program.programId
+    );
+    await runTimelocked('setCandyMachineRewardPerToken', [candyMachine, reward], {
+        // Stake instance.
+        pool: poolPubkey,
+        vault: _vaultPubkey,
+        cmRewardPerToken: cmRewardPerToken,
+        authority: provider.wallet.publicKey,
+        systemProgram: anchor.web3.SystemProgram.programId,
+    });
+}
+
+const removeCandyMachineRewardPerToken = async () => {
+    if (!values[0]) {
+        console.log('Missing an arguments.\n\nyarn remove_cm_reward_per_token <CANDY_MACHINE_ID>');
+        return;
+    }
+
+    const candyMachine = new anchor.web3.PublicKey(values[0]);
+
+    const [
+        cmRewardPerToken,
+        nonce,
+    ] = await anchor.web3.PublicKey.findProgramAddress(
+        [
+            poolPubkey.toBuffer(),
+            Buffer.from('reward_per_token')
+        ],
+        program.programId
+    );
+    const [
+        _vaultPubkey,
+        _vaultNonce,
+    ] = await anchor.web3.PublicKey.findProgramAddress(
+        [provider.wallet.publicKey.toBuffer(), poolPubkey.toBuffer()],
+        program.programId
+    );
+    await runTimelocked('removeCandyMachineRewardPerToken', [candyMachine], {
+        // Stake instance.
+        pool: poolPubkey,
+        vault: _vaultPubkey,
+        cmRewardPerToken: cmRewardPerToken,
+        authority: provider.wallet.publicKey,
+        systemProgram: anchor.web3.SystemProgram.programId,
+    });
+}
+
+const createRewardStreams = async () => {
+    const [
+        rewardStreams,
+        nonce,
+    ] = await anchor.web3.PublicKey.findProgramAddress(
+        [
+            poolPubkey.toBuffer(),
+            Buffer.from('reward_streams')
+        ],
+        program.programId
+    );
+
+    await program.rpc.createRewardStreams(nonce, {
         accounts: {
             // Stake instance.
             pool: poolPubkey,
-            cmRewardPerToken: cmRewardPerToken,
+            rewardStreams: rewardStreams,
             authority: provider.wallet.publicKey,
             systemProgram: anchor.web3.SystemProgram.programId,
         },
     });
 }
 
-const removeCandyMachineRewardPerToken = async () => {
+const createSetBonus = async () => {
+    const [
+        setBonus,
+        nonce,
+    ] = await anchor.web3.PublicKey.findProgramAddress(
+        [
+            poolPubkey.toBuffer(),
+            Buffer.from('set_bonus')
+        ],
+        program.programId
+    );
+
+    await program.rpc.createSetBonus(nonce, {
+        accounts: {
+            // Stake instance.
+            pool: poolPubkey,
+            setBonus: setBonus,
+            authority: provider.wallet.publicKey,
+            systemProgram: anchor.web3.SystemProgram.programId,
+        },
+    });
+}
+
+const setSetBonus = async () => {
     if (!values[0]) {
-        console.log('Missing an arguments.\n\nyarn remove_cm_reward_per_token <CANDY_MACHINE_ID>');
+        console.log('Missing some arguments.\n\nyarn set_set_bonus <BONUS_BPS> [<CANDY_MACHINE_ID> ...]');
         return;
     }
 
-    const candyMachine = new anchor.web3.PublicKey(values[0]);
+    const bonusBps = Number(values[0]);
+    const candyMachines = values.slice(1).map(x => new anchor.web3.PublicKey(x));
 
+    const [
+        setBonus,
+    ] = await anchor.web3.PublicKey.findProgramAddress(
+        [
+            poolPubkey.toBuffer(),
+            Buffer.from('set_bonus')
+        ],
+        program.programId
+    );
+
+    await runTimelocked('setSetBonus', [candyMachines, bonusBps], {
+        // Stake instance.
+        pool: poolPubkey,
+        setBonus: setBonus,
+        authority: provider.wallet.publicKey,
+    });
+}
+
+const PAUSE_FLAGS = { stake: 1, claim: 2, admin: 4, all: 7 };
+
+const setPaused = async (paused) => {
+    if (!values[0]) {
+        console.log('Missing some arguments.\n\nyarn ' + (paused ? 'pause' : 'unpause') + ' <stake|claim|admin|all> [...]');
+        return;
+    }
+
+    let flags = 0;
+    for (const value of values) {
+        if (!PAUSE_FLAGS[value]) {
+            console.log('Unknown pause flag: ', value);
+            return;
+        }
+        flags |= PAUSE_FLAGS[value];
+    }
+
+    const [
+        poolSigner,
+    ] = await anchor.web3.PublicKey.findProgramAddress(
+        [poolPubkey.toBuffer()],
+        program.programId
+    );
+    await program.rpc[paused ? 'pause' : 'unpause'](flags, {
+        accounts: {
+            pool: poolPubkey,
+            authority: provider.wallet.publicKey,
+            poolSigner,
+            tokenProgram: TOKEN_PROGRAM_ID,
+        },
+    });
+}
+
+const reportSolvency = async () => {
+    let poolObject = await program.account.pool.fetch(poolPubkey);
+
+    const [
+        _vaultPubkey,
+        _vaultNonce,
+    ] = await anchor.web3.PublicKey.findProgramAddress(
+        [poolObject.authority.toBuffer(), poolPubkey.toBuffer()],
+        program.programId
+    );
     const [
         cmRewardPerToken,
         nonce,
@@ -283,15 +797,20 @@ const removeCandyMachineRewardPerToken = async () => {
         ],
         program.programId
     );
-    await program.rpc.removeCandyMachineRewardPerToken(candyMachine, {
+
+    const result = await program.simulate.reportSolvency({
         accounts: {
-            // Stake instance.
             pool: poolPubkey,
+            vault: _vaultPubkey,
             cmRewardPerToken: cmRewardPerToken,
-            authority: provider.wallet.publicKey,
-            systemProgram: anchor.web3.SystemProgram.programId,
+            rewardVault: poolObject.rewardVault,
         },
     });
+    const report = result.events.find(e => e.name == 'SolvencyReport').data;
+    console.log("Reward vault balance: ", report.rewardVaultBalance.toString());
+    console.log("Reward liability: ", report.rewardLiability.toString());
+    console.log("Emission per day: ", report.emissionPerDay.toString());
+    console.log("Runway (seconds): ", report.runway.toString());
 }
 
 console.log("Program ID: ", programID.toString());
@@ -307,7 +826,27 @@ const commandID = argv.indexOf('--command_id=1') > -1 ? 1 :
                             argv.indexOf('--command_id=8') > -1 ? 8 :
                                 argv.indexOf('--command_id=9') > -1 ? 9 :
                                     argv.indexOf('--command_id=10') > -1 ? 10 :
-                                        argv.indexOf('--command_id=11') > -1 ? 11 : -1;
+                                        argv.indexOf('--command_id=11') > -1 ? 11 :
+                                            argv.indexOf('--command_id=12') > -1 ? 12 :
+                                                argv.indexOf('--command_id=13') > -1 ? 13 :
+                                                    argv.indexOf('--command_id=14') > -1 ? 14 :
+                                                        argv.indexOf('--command_id=15') > -1 ? 15 :
+                                                            argv.indexOf('--command_id=16') > -1 ? 16 :
+                                                                argv.indexOf('--command_id=17') > -1 ? 17 :
+                                                                    argv.indexOf('--command_id=18') > -1 ? 18 :
+                                                                        argv.indexOf('--command_id=19') > -1 ? 19 :
+                                                                            argv.indexOf('--command_id=20') > -1 ? 20 :
+                                                                                argv.indexOf('--command_id=21') > -1 ? 21 :
+                                                                                    argv.indexOf('--command_id=22') > -1 ? 22 :
+                                                                                        argv.indexOf('--command_id=23') > -1 ? 23 :
+                                                                                        argv.indexOf('--command_id=24') > -1 ? 24 :
+                                                                                            argv.indexOf('--command_id=25') > -1 ? 25 :
+                                                                                                argv.indexOf('--command_id=26') > -1 ? 26 :
+                                                                                                    argv.indexOf('--command_id=27') > -1 ? 27 :
+                                                                                                        argv.indexOf('--command_id=28') > -1 ? 28 :
+                                                                                                            argv.indexOf('--command_id=29') > -1 ? 29 :
+                                                                                                            argv.indexOf('--command_id=30') > -1 ? 30 :
+                                                                                                                argv.indexOf('--command_id=31') > -1 ? 31 : -1;
 switch (commandID) {
     case 1:
         setRewardPerToken();
@@ -339,6 +878,66 @@ switch (commandID) {
     case 11:
         removeCandyMachineRewardPerToken();
         break;
+    case 12:
+        reportSolvency();
+        break;
+    case 13:
+        createRewardStreams();
+        break;
+    case 14:
+        setEmissionSchedule();
+        break;
+    case 15:
+        setEarlyUnstakePenalty();
+        break;
+    case 16:
+        setLoyaltyMultipliers();
+        break;
+    case 17:
+        setRarityRoot();
+        break;
+    case 18:
+        setTierMultipliers();
+        break;
+    case 19:
+        createSetBonus();
+        break;
+    case 20:
+        setSetBonus();
+        break;
+    case 21:
+        proposeAuthority();
+        break;
+    case 22:
+        acceptAuthority();
+        break;
+    case 23:
+        setRoles();
+        break;
+    case 24:
+        executeSetRewardPerToken();
+        break;
+    case 25:
+        executeWithdrawReward();
+        break;
+    case 26:
+        cancelPendingAction();
+        break;
+    case 27:
+        setTimelockDelay();
+        break;
+    case 28:
+        queueSetTimelockDelay();
+        break;
+    case 29:
+        executeSetTimelockDelay();
+        break;
+    case 30:
+        setPaused(true);
+        break;
+    case 31:
+        setPaused(false);
+        break;
     default:
         console.log('Unrecognized command');
         break;
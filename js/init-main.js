@@ -74,6 +74,8 @@ const initializePool = async () => {
     await program.rpc.initializePool(
         poolNonce,
         vaultNonce,
+        // Timelock delay for queued admin actions: 2 days.
+        new anchor.BN(2 * 24 * 60 * 60),
         {
             accounts: {
                 authority: provider.wallet.publicKey,
@@ -1,5 +1,8 @@
 use anchor_lang::prelude::*;
 use anchor_lang::solana_program::{clock, program_option::COption};
+use anchor_lang::solana_program::instruction::{AccountMeta, Instruction};
+use anchor_lang::solana_program::program::{invoke, invoke_signed};
+use anchor_lang::solana_program::{keccak, system_instruction};
 use anchor_spl::token::{self, TokenAccount, Token, Mint};
 use std::convert::Into;
 use std::convert::TryInto;
@@ -7,548 +10,3504 @@ use metaplex_token_metadata::state::{Metadata};
 
 declare_id!("72DPSeKRFpnz6qaQByKkq3NnLYRw37His4QTJBoH4xDN");
 
-pub fn update_rewards(
-    pool: &mut Account<Pool>,
-    u: &mut Box<Account<User>>,
-    user_store: &mut Box<Account<UserStore>>,
-    vault: &mut Box<Account<Vault>>,
-    cm_reward_per_tokens: &mut Box<Account<CandyMachineRewardPerToken>>,
-) -> Result<()> {
-    let clock = clock::Clock::get().unwrap();
-
-    let current_time: u64 = clock.unix_timestamp.try_into().unwrap();
-    let mut reward_token_pending: u64 = 0;
-    for i in 0..user_store.types.len() {
-        let nft_type = user_store.types[i];
-        let index = vault.reward_types.iter().position(|&x| x == nft_type);
-        let mut reward_per_token = pool.reward_per_token;
-        if index != None {
-            let candy_machine = vault.candy_machines[index.unwrap()];
-            let index = cm_reward_per_tokens.candy_machines.iter().position(|&x| x == candy_machine);
-            if index != None {
-                reward_per_token = cm_reward_per_tokens.reward_per_tokens[index.unwrap()]
-            }
-        }
-    
-        let staked_time = user_store.staked_times[i];
-        let diff_times: u64 = current_time.checked_sub(staked_time).unwrap();
-        user_store.staked_times[i] = current_time;
-        reward_token_pending = reward_token_pending.checked_add(
-                                    reward_per_token.checked_div(60 * 60 * 24).unwrap()
-                                                    .checked_mul(diff_times).unwrap()
-                                ).unwrap()
-    }
+const SECONDS_PER_DAY: u64 = 60 * 60 * 24;
+/// Fixed-point scale used for fractional reward accrual.
+const REWARD_PRECISION: u128 = 1_000_000_000_000;
+/// Most NFTs a UserStore can hold.
+const MAX_NFTS_PER_STORE: usize = 100;
+/// Largest a UserStore may grow to.
+const MAX_USER_STORE_SPACE: usize = 10240;
+/// Basis points in one whole, used for emission schedule steps.
+const BPS: u64 = 10_000;
+/// Most steps an emission schedule can take between its start and end.
+const MAX_EMISSION_STEPS: u64 = 256;
+/// Most steps a loyalty multiplier curve can have.
+const MAX_LOYALTY_STEPS: usize = 8;
+/// Most rarity tiers a pool can price.
+const MAX_RARITY_TIERS: usize = 16;
+/// Most distinct candy machines a user's stake counters can track.
+const MAX_USER_CANDY_MACHINES: usize = 32;
+/// Account size of User, with room for its per-candy-machine counters.
+const USER_SPACE: usize = 8 + 32 + 32 + 8 + 8 + 1 + 1 + 1
+                          + (4 + 32 * MAX_USER_CANDY_MACHINES)
+                          + (4 + 4 * MAX_USER_CANDY_MACHINES)
+                          + 16 + 8 + 16;
+/// Most candy machines a set-completion bonus can require.
+const MAX_SET_BONUS_CANDY_MACHINES: usize = 16;
+/// Pool pause flags. Unstaking is never paused so NFTs can always be taken back.
+const PAUSE_STAKE: u8 = 1 << 0;
+const PAUSE_CLAIM: u8 = 1 << 1;
+const PAUSE_ADMIN: u8 = 1 << 2;
+const PAUSE_ALL: u8 = PAUSE_STAKE | PAUSE_CLAIM | PAUSE_ADMIN;
+/// Kinds of timelocked admin action. Kinds from ACTION_SET_EMISSION_SCHEDULE on gate the
+/// instruction of the same name, called with the arguments they were queued with.
+const ACTION_SET_REWARD_PER_TOKEN: u8 = 0;
+const ACTION_WITHDRAW_REWARD: u8 = 1;
+const ACTION_SET_TIMELOCK_DELAY: u8 = 2;
+const ACTION_SET_EMISSION_SCHEDULE: u8 = 3;
+const ACTION_SET_CANDY_MACHINE_REWARD_PER_TOKEN: u8 = 4;
+const ACTION_REMOVE_CANDY_MACHINE_REWARD_PER_TOKEN: u8 = 5;
+const ACTION_SET_REWARD_STREAM: u8 = 6;
+const ACTION_SET_LOYALTY_MULTIPLIERS: u8 = 7;
+const ACTION_SET_TIER_MULTIPLIERS: u8 = 8;
+const ACTION_SET_SET_BONUS: u8 = 9;
+const ACTION_SET_EARLY_UNSTAKE_PENALTY: u8 = 10;
+const ACTION_REMOVE_CANDY_MACHINE: u8 = 11;
+const ACTION_SET_RARITY_ROOT: u8 = 12;
+/// Longest encoded arguments a queued action can hold, those of `set_set_bonus`.
+const MAX_ACTION_ARGS: usize = (4 + 32 * MAX_SET_BONUS_CANDY_MACHINES) + 2;
+/// Most extra reward streams a pool can run, bounded by claim's account list.
+const MAX_REWARD_STREAMS: usize = 8;
+/// Accounts passed per NFT to `stake_many`/`unstake_many`.
+const STAKE_ACCOUNT_GROUP_LEN: usize = 4;
+/// Token metadata instructions used for soft staking.
+const FREEZE_DELEGATED_ACCOUNT: u8 = 26;
+const THAW_DELEGATED_ACCOUNT: u8 = 27;
 
-    user_store.reward_token_pending = user_store.reward_token_pending.checked_add(reward_token_pending).unwrap();
-    u.last_update_time = current_time;
-    
-    Ok(())
+/// Current cluster time in seconds.
+pub fn current_timestamp() -> Result<u64> {
+    let clock = clock::Clock::get()?;
+    clock.unix_timestamp.try_into().map_err(|_| ErrorCode::InvalidTimestamp.into())
 }
 
-#[program]
-pub mod j_nft_staking {
-    use super::*;
+/// Daily reward paid per staked NFT of `candy_machine`.
+pub fn candy_machine_reward_per_token(
+    pool: &Pool,
+    cm_reward_per_tokens: &CandyMachineRewardPerToken,
+    candy_machine: Pubkey,
+) -> u64 {
+    let index = cm_reward_per_tokens.candy_machines.iter().position(|&x| x == candy_machine);
+    match index {
+        Some(index) => cm_reward_per_tokens.reward_per_tokens[index],
+        None => pool.reward_per_token,
+    }
+}
 
-    pub fn initialize_pool(
-        ctx: Context<InitializePool>,
-        pool_nonce: u8,
-        vault_nonce: u8,
-    ) -> Result<()> {
-        let pool = &mut ctx.accounts.pool;
+/// Rewards accrued by one NFT at `reward_per_token` over `diff_times`, scaled by REWARD_PRECISION.
+pub fn reward_per_share(reward_per_token: u64, diff_times: u64) -> Result<u128> {
+    let accrued = (reward_per_token as u128).checked_mul(diff_times as u128).ok_or(ErrorCode::MathOverflow)?
+                                            .checked_mul(REWARD_PRECISION).ok_or(ErrorCode::MathOverflow)?
+                                            .checked_div(SECONDS_PER_DAY as u128).ok_or(ErrorCode::MathOverflow)?;
+    Ok(accrued)
+}
 
-        pool.authority = ctx.accounts.authority.key();
-        pool.nonce = pool_nonce;
-        pool.paused = false;
-        pool.reward_mint = ctx.accounts.reward_mint.key();
-        pool.reward_vault = ctx.accounts.reward_vault.key();
-        pool.reward_per_token = 1_1000_000_000;
-        pool.user_stake_count = 0;
-        pool.balance_staked = 0;
+/// `step_bps` applied `steps` times over, in basis points, by repeated squaring.
+pub fn compounded_bps(step_bps: u64, steps: u64) -> u64 {
+    let (mut multiplier, mut factor, mut steps) = (BPS, step_bps, steps);
+    while steps > 0 && multiplier > 0 {
+        if steps & 1 == 1 {
+            multiplier = multiplier * factor / BPS;
+        }
+        factor = factor * factor / BPS;
+        steps >>= 1;
+    }
+    multiplier
+}
 
-        let vault = &mut ctx.accounts.vault;
-        vault.nonce = vault_nonce;
-        vault.candy_machines = vec![];
-        vault.reward_types = vec![];
-        
-        Ok(())
+/// Share of the base rate emitted at `time`, in basis points. Zero outside the pool's emission window.
+pub fn emission_multiplier(pool: &Pool, time: u64) -> u64 {
+    if time < pool.emission_start_time || time >= pool.emission_end_time {
+        return 0;
+    }
+    match (time - pool.emission_start_time).checked_div(pool.emission_step_interval) {
+        Some(steps) => compounded_bps(pool.emission_step_bps as u64, steps),
+        None => BPS,
     }
+}
 
-    pub fn set_reward_per_token(ctx: Context<SetRewardPerToken>, reward_per_token: u64) -> Result<()> {
-        let pool = &mut ctx.accounts.pool;
-        pool.reward_per_token = reward_per_token;
+/// Seconds between `from` and `to` weighted by the emission schedule, in basis points of the base rate.
+pub fn emitted_time(pool: &Pool, from: u64, to: u64) -> Result<u128> {
+    let from = from.max(pool.emission_start_time);
+    let to = to.min(pool.emission_end_time);
+    if to <= from {
+        return Ok(0);
+    }
+    if pool.emission_step_interval == 0 || pool.emission_step_bps as u64 == BPS {
+        return ((to - from) as u128).checked_mul(BPS as u128).ok_or_else(|| ErrorCode::MathOverflow.into());
+    }
 
-        Ok(())
+    // Start at the step `from` falls in and walk only the steps up to `to`; set_emission_schedule
+    // caps how many there are.
+    let step = (from - pool.emission_start_time) / pool.emission_step_interval;
+    let mut total: u128 = 0;
+    let mut multiplier = compounded_bps(pool.emission_step_bps as u64, step);
+    let mut step_start = pool.emission_start_time + step * pool.emission_step_interval;
+    while step_start < to && multiplier > 0 {
+        let step_end = step_start.saturating_add(pool.emission_step_interval);
+        let (a, b) = (from.max(step_start), to.min(step_end));
+        if b > a {
+            total = total.checked_add(((b - a) as u128).checked_mul(multiplier as u128).ok_or(ErrorCode::MathOverflow)?)
+                         .ok_or(ErrorCode::MathOverflow)?;
+        }
+        step_start = step_end;
+        multiplier = multiplier * pool.emission_step_bps as u64 / BPS;
     }
+    Ok(total)
+}
 
-    pub fn create_candy_machine_reward_per_token(ctx: Context<CreateCandyMachineRewardPerToken>, nonce: u8) -> Result<()> {
-        let cm_reward_per_token = &mut ctx.accounts.cm_reward_per_token;
-        cm_reward_per_token.nonce = nonce;
-        cm_reward_per_token.candy_machines = vec![];
-        cm_reward_per_token.reward_per_tokens = vec![];
+/// Rewards accrued by one NFT at base rate `reward_per_token` over `emitted_time` (see `emitted_time`),
+/// scaled by REWARD_PRECISION.
+pub fn scheduled_reward_per_share(reward_per_token: u64, emitted_time: u128) -> Result<u128> {
+    let accrued = (reward_per_token as u128).checked_mul(emitted_time).ok_or(ErrorCode::MathOverflow)?
+                                            .checked_mul(REWARD_PRECISION).ok_or(ErrorCode::MathOverflow)?
+                                            .checked_div((SECONDS_PER_DAY as u128) * (BPS as u128)).ok_or(ErrorCode::MathOverflow)?;
+    Ok(accrued)
+}
 
-        Ok(())
+/// Brings every candy machine's reward-per-NFT accumulator and the pool liability up to `current_time`.
+pub fn update_reward_pool(
+    pool: &mut Account<Pool>,
+    vault: &mut Box<Account<Vault>>,
+    cm_reward_per_tokens: &Account<CandyMachineRewardPerToken>,
+    current_time: u64,
+) -> Result<()> {
+    let diff_times: u64 = current_time.checked_sub(vault.last_update_time).ok_or(ErrorCode::InvalidTimestamp)?;
+    if diff_times == 0 {
+        return Ok(());
     }
 
-    pub fn set_candy_machine_reward_per_token(ctx: Context<SetCandyMachineRewardPerToken>, candy_machine: Pubkey, reward_per_token: u64) -> Result<()> {
-        let cm_reward_per_token = &mut ctx.accounts.cm_reward_per_token;
-        let index = cm_reward_per_token.candy_machines.iter().position(|&x| x == candy_machine);
-        if index == None {
-            cm_reward_per_token.candy_machines.push(candy_machine);
-            cm_reward_per_token.reward_per_tokens.push(reward_per_token);
-        } else {
-            cm_reward_per_token.reward_per_tokens[index.unwrap()] = reward_per_token;
+    let emitted = emitted_time(pool, vault.last_update_time, current_time)?;
+    for i in 0..vault.candy_machines.len() {
+        // A removed candy machine's accumulator stays frozen for its remaining stakers to settle against.
+        if vault.retired[i] {
+            continue;
         }
+        let reward_per_token = candy_machine_reward_per_token(pool, cm_reward_per_tokens, vault.candy_machines[i]);
+        let accrued = scheduled_reward_per_share(reward_per_token, emitted)?;
 
-        Ok(())
+        vault.acc_reward_per_shares[i] = vault.acc_reward_per_shares[i].checked_add(accrued).ok_or(ErrorCode::MathOverflow)?;
+        pool.reward_liability = pool.reward_liability.checked_add(
+                                    (vault.staked_weights[i] as u128).checked_mul(accrued).ok_or(ErrorCode::MathOverflow)?
+                                                                     .checked_div(BPS as u128).ok_or(ErrorCode::MathOverflow)?
+                                ).ok_or(ErrorCode::MathOverflow)?;
     }
+    vault.last_update_time = current_time;
 
-    pub fn remove_candy_machine_reward_per_token(ctx: Context<SetCandyMachineRewardPerToken>, candy_machine: Pubkey) -> Result<()> {
-        let cm_reward_per_token = &mut ctx.accounts.cm_reward_per_token;
-        let index = cm_reward_per_token.candy_machines.iter().position(|&x| x == candy_machine);
-        if index != None {
-            cm_reward_per_token.candy_machines.remove(index.unwrap());
-            cm_reward_per_token.reward_per_tokens.remove(index.unwrap());
-        }
+    Ok(())
+}
 
-        Ok(())
+/// Brings every reward stream's accumulator up to `current_time`, counting only time inside the stream's window.
+pub fn update_reward_streams(reward_streams: &mut RewardStreams, current_time: u64) -> Result<()> {
+    for stream in reward_streams.streams.iter_mut() {
+        let from = stream.last_update_time.max(stream.start_time);
+        let to = current_time.min(stream.end_time);
+        if to > from {
+            let accrued = reward_per_share(stream.reward_per_token, to - from)?;
+            stream.acc_reward_per_share = stream.acc_reward_per_share.checked_add(accrued).ok_or(ErrorCode::MathOverflow)?;
+        }
+        stream.last_update_time = current_time;
     }
 
-    pub fn create_user(ctx: Context<CreateUser>, nonce: u8, store_nonce: u8) -> Result<()> {
-        let user = &mut ctx.accounts.user;
-        user.pool = *ctx.accounts.pool.to_account_info().key;
-        user.owner = *ctx.accounts.owner.key;
-        user.balance_staked = 0;
-        user.stores = 1;
+    Ok(())
+}
 
-        let current_time = clock::Clock::get().unwrap().unix_timestamp.try_into().unwrap();
+/// Checks that `proof` places (`mint`, `tier`) under the pool's rarity Merkle root.
+/// Tier 0 is the default for unlisted NFTs and needs no proof.
+pub fn verify_rarity_tier(pool: &Pool, mint: &Pubkey, tier: u8, proof: &[[u8; 32]]) -> Result<()> {
+    if tier == 0 {
+        return Ok(());
+    }
+    let mut node = keccak::hashv(&[mint.as_ref(), &[tier]]).0;
+    for sibling in proof {
+        // Pairs are hashed in sorted order so the proof needs no left/right flags.
+        node = if node <= *sibling {
+            keccak::hashv(&[&node, sibling]).0
+        } else {
+            keccak::hashv(&[sibling, &node]).0
+        };
+    }
+    if node != pool.rarity_root {
+        return Err(ErrorCode::InvalidRarityProof.into());
+    }
 
-        user.last_update_time = current_time;
-        user.nonce = nonce;
+    Ok(())
+}
 
-        let user_store = &mut ctx.accounts.user_store;
-        user_store.nft_mints = vec![];
-        user_store.types = vec![];
-        user_store.staked_times = vec![];
-        user_store.owner = *ctx.accounts.owner.key;
-        user_store.nonce = store_nonce;
-        user_store.store_id = user.stores;
-        user_store.reward_token_pending = 0;
+/// Reward weight in basis points of an NFT of rarity `tier` staked for `staked_duration` seconds.
+pub fn nft_weight(vault: &Vault, tier: u8, staked_duration: u64) -> u64 {
+    let tier_multiplier = vault.tier_multiplier_bps.get(tier as usize).map_or(BPS, |&x| x as u64);
+    loyalty_multiplier(vault, staked_duration).saturating_mul(tier_multiplier) / BPS
+}
+
+/// Reward multiplier in basis points for an NFT staked for `staked_duration` seconds.
+pub fn loyalty_multiplier(vault: &Vault, staked_duration: u64) -> u64 {
+    let mut multiplier = BPS;
+    for (i, &days) in vault.loyalty_days.iter().enumerate() {
+        if staked_duration >= (days as u64).saturating_mul(SECONDS_PER_DAY) {
+            multiplier = vault.loyalty_multiplier_bps[i] as u64;
+        }
+    }
+    multiplier
+}
 
+/// First time after `after` at which an NFT staked at `staked_time` reaches a loyalty step; u64::MAX if none.
+pub fn next_loyalty_step(vault: &Vault, staked_time: u64, after: u64) -> u64 {
+    vault.loyalty_days.iter()
+                      .map(|&days| staked_time.saturating_add((days as u64).saturating_mul(SECONDS_PER_DAY)))
+                      .find(|&step| step > after)
+                      .unwrap_or(u64::MAX)
+}
 
-        let pool = &mut ctx.accounts.pool;
-        pool.user_stake_count = pool.user_stake_count.checked_add(1).unwrap();
+/// Whether any NFT of the store may have changed weight since its last update: one reached a loyalty
+/// step or the multipliers changed. Stores that don't need no per-NFT work.
+pub fn nft_weights_due(user_store: &UserStore, vault: &Vault, current_time: u64) -> bool {
+    current_time >= user_store.next_weight_update || user_store.last_update_time <= vault.multipliers_updated_at
+}
 
-        Ok(())
+/// Corrects the store's accrual, scaled by REWARD_PRECISION and BPS, for NFTs that reached a loyalty step
+/// since its last update: the interval was settled at their old weight, but from each step on they earn at
+/// that step's weight. Each candy machine's accrual is split at the step in proportion to the emission
+/// schedule. Returns what they gained and lost.
+pub fn loyalty_step_adjustment(pool: &Pool, user_store: &UserStore, vault: &Vault, current_time: u64) -> Result<(u128, u128)> {
+    let last_update_time = user_store.last_update_time;
+    let emitted = emitted_time(pool, last_update_time, current_time)?;
+    let (mut gained, mut lost): (u128, u128) = (0, 0);
+    if emitted == 0 {
+        return Ok((gained, lost));
     }
 
-    pub fn create_user_store(ctx: Context<CreateUserStore>, nonce: u8) -> Result<()> {
-        let user = &mut ctx.accounts.user;
-        user.stores = user.stores.checked_add(1).unwrap();
-
-        let user_store = &mut ctx.accounts.user_store;
-        user_store.nft_mints = vec![];
-        user_store.types = vec![];
-        user_store.staked_times = vec![];
-        user_store.owner = *ctx.accounts.owner.key;
-        user_store.nonce = nonce;
-        user_store.store_id = user.stores;
-        user_store.reward_token_pending = 0;
+    for i in 0..user_store.nft_mints.len() {
+        let candy_machine = user_store.nft_candy_machines[i];
+        let vault_index = match vault.candy_machines.iter().position(|&x| x == candy_machine) {
+            Some(vault_index) => vault_index,
+            None => continue,
+        };
+        let index = user_store.candy_machines.iter().position(|&x| x == candy_machine).ok_or(ErrorCode::NftNotStaked)?;
+        let staked_weight = user_store.staked_weights[index] as u128;
+        if staked_weight == 0 {
+            continue;
+        }
+        // The debt was last synced to weight * accumulator, so it gives the accumulator at the last update.
+        let settled_reward_per_share = user_store.reward_debts[index] / staked_weight;
+        let accrued = vault.acc_reward_per_shares[vault_index].checked_sub(settled_reward_per_share).ok_or(ErrorCode::MathOverflow)?;
 
-        Ok(())
+        let staked_time = user_store.staked_times[i];
+        let mut weight = user_store.nft_weights[i];
+        let mut step = next_loyalty_step(vault, staked_time, last_update_time);
+        while step <= current_time {
+            let step_weight = nft_weight(vault, user_store.tiers[i], step - staked_time);
+            if step_weight != weight {
+                let accrued_since = accrued.checked_mul(emitted_time(pool, step, current_time)?).ok_or(ErrorCode::MathOverflow)?
+                                           .checked_div(emitted).ok_or(ErrorCode::MathOverflow)?;
+                if step_weight > weight {
+                    gained = gained.checked_add(((step_weight - weight) as u128).checked_mul(accrued_since).ok_or(ErrorCode::MathOverflow)?)
+                                   .ok_or(ErrorCode::MathOverflow)?;
+                } else {
+                    lost = lost.checked_add(((weight - step_weight) as u128).checked_mul(accrued_since).ok_or(ErrorCode::MathOverflow)?)
+                               .ok_or(ErrorCode::MathOverflow)?;
+                }
+                weight = step_weight;
+            }
+            step = next_loyalty_step(vault, staked_time, step);
+        }
     }
 
-    pub fn pause(ctx: Context<Pause>) -> Result<()> {
-        let pool = &mut ctx.accounts.pool;
-        pool.paused = true;
+    Ok((gained, lost))
+}
 
-        Ok(())
+/// Moves each staked NFT to the weight it has reached by `current_time` under the current loyalty
+/// and tier multipliers, updating the store and vault weights and the store's next loyalty step.
+/// Rewards must already be settled.
+pub fn refresh_nft_weights(
+    user_store: &mut Box<Account<UserStore>>,
+    vault: &mut Box<Account<Vault>>,
+    current_time: u64,
+) -> Result<()> {
+    let mut next_weight_update = u64::MAX;
+    for i in 0..user_store.nft_mints.len() {
+        next_weight_update = next_weight_update.min(next_loyalty_step(vault, user_store.staked_times[i], current_time));
+        let weight = nft_weight(vault, user_store.tiers[i], current_time.saturating_sub(user_store.staked_times[i]));
+        let old_weight = user_store.nft_weights[i];
+        if weight == old_weight {
+            continue;
+        }
+        user_store.nft_weights[i] = weight;
+
+        let candy_machine = user_store.nft_candy_machines[i];
+        let index = user_store.candy_machines.iter().position(|&x| x == candy_machine).ok_or(ErrorCode::NftNotStaked)?;
+        user_store.staked_weights[index] = user_store.staked_weights[index].checked_add(weight).ok_or(ErrorCode::MathOverflow)?
+                                                                           .checked_sub(old_weight).ok_or(ErrorCode::MathOverflow)?;
+        // A still-locked NFT's penalty follows its current weight.
+        if user_store.unlock_times[i] > current_time {
+            let entry = user_store.entry_reward_per_shares[i];
+            user_store.locked_weights[index] = user_store.locked_weights[index].checked_add(weight).ok_or(ErrorCode::MathOverflow)?
+                                                                               .checked_sub(old_weight).ok_or(ErrorCode::MathOverflow)?;
+            user_store.locked_entry_shares[index] = user_store.locked_entry_shares[index]
+                                                        .checked_add((weight as u128).checked_mul(entry).ok_or(ErrorCode::MathOverflow)?)
+                                                        .ok_or(ErrorCode::MathOverflow)?
+                                                        .checked_sub((old_weight as u128).checked_mul(entry).ok_or(ErrorCode::MathOverflow)?)
+                                                        .ok_or(ErrorCode::MathOverflow)?;
+        }
+        let index = vault.candy_machines.iter().position(|&x| x == candy_machine).ok_or(ErrorCode::NftNotStaked)?;
+        vault.staked_weights[index] = vault.staked_weights[index].checked_add(weight).ok_or(ErrorCode::MathOverflow)?
+                                                                 .checked_sub(old_weight).ok_or(ErrorCode::MathOverflow)?;
     }
+    user_store.next_weight_update = next_weight_update;
 
-    pub fn unpause(ctx: Context<Unpause>) -> Result<()> {
-        let pool = &mut ctx.accounts.pool;
-        pool.paused = false;
+    Ok(())
+}
 
-        Ok(())
+/// Takes the NFT at `index` out of its candy machine's locked sums. They count an NFT until the
+/// first store update at or after its unlock time.
+pub fn release_locked_nft(user_store: &mut UserStore, index: usize) {
+    if user_store.unlock_times[index] <= user_store.last_update_time {
+        return;
     }
+    let weight = user_store.nft_weights[index];
+    let candy_machine = user_store.nft_candy_machines[index];
+    if let Some(store_index) = user_store.candy_machines.iter().position(|&x| x == candy_machine) {
+        user_store.locked_weights[store_index] = user_store.locked_weights[store_index].saturating_sub(weight);
+        user_store.locked_entry_shares[store_index] = user_store.locked_entry_shares[store_index].saturating_sub(
+                                                          (weight as u128).saturating_mul(user_store.entry_reward_per_shares[index])
+                                                      );
+    }
+}
 
-    pub fn add_candy_machine(
-                                ctx: Context<ManageCandyMachine>, 
-                                candy_machine: Pubkey, 
-                                reward_type: u8,
-                            ) -> Result<()> {
-        let vault = &mut ctx.accounts.vault;
-        let index = vault.candy_machines.iter().position(|&x| x == candy_machine);
-        if index == None {
-            vault.candy_machines.push(candy_machine);
-            vault.reward_types.push(reward_type);
+/// Releases the NFTs that unlocked since the store's last update and indexes the next unlock.
+pub fn release_unlocked_nfts(user_store: &mut UserStore, current_time: u64) {
+    let mut next_unlock_time = u64::MAX;
+    for i in 0..user_store.nft_mints.len() {
+        let unlock_time = user_store.unlock_times[i];
+        if unlock_time <= current_time {
+            release_locked_nft(user_store, i);
         } else {
-            vault.reward_types[index.unwrap()] = reward_type;
+            next_unlock_time = next_unlock_time.min(unlock_time);
         }
-        Ok(())
     }
+    user_store.next_unlock_time = next_unlock_time;
+}
 
-    pub fn remove_candy_machine(ctx: Context<ManageCandyMachine>, 
-                                candy_machine: Pubkey, ) -> Result<()> {
-        let vault = &mut ctx.accounts.vault;
-        let index = vault.candy_machines.iter().position(|&x| x == candy_machine);
-        if index != None {
-            vault.candy_machines.remove(index.unwrap());
-            vault.reward_types.remove(index.unwrap());
+/// Reward tokens the store's still-locked NFTs would forfeit by unstaking early: the sum of their
+/// `early_unstake_penalty`, from the locked sums in one pass over the store's candy machines.
+pub fn store_withheld(vault: &Vault, user_store: &UserStore) -> Result<u64> {
+    let mut withheld: u128 = 0;
+    for i in 0..user_store.candy_machines.len() {
+        if user_store.locked_weights[i] == 0 {
+            continue;
         }
-        Ok(())
+        let vault_index = match vault.candy_machines.iter().position(|&x| x == user_store.candy_machines[i]) {
+            Some(vault_index) => vault_index,
+            None => continue,
+        };
+        let accrued = (user_store.locked_weights[i] as u128).checked_mul(vault.acc_reward_per_shares[vault_index])
+                                                            .ok_or(ErrorCode::MathOverflow)?
+                                                            .checked_sub(user_store.locked_entry_shares[i])
+                                                            .ok_or(ErrorCode::MathOverflow)?;
+        withheld = withheld.checked_add(
+                       accrued.checked_mul(vault.early_unstake_penalty_bps[vault_index] as u128).ok_or(ErrorCode::MathOverflow)?
+                   ).ok_or(ErrorCode::MathOverflow)?;
     }
+    let withheld = withheld.checked_div((BPS as u128) * (BPS as u128) * REWARD_PRECISION).ok_or(ErrorCode::MathOverflow)?;
+    withheld.try_into().map_err(|_| ErrorCode::MathOverflow.into())
+}
 
-    pub fn stake(ctx: Context<Stake>) -> Result<()> {
-        let pool = &mut ctx.accounts.pool;
-        if pool.paused {
-            return Err(ErrorCode::PoolPaused.into());
-        }
-        msg!("staking start");
-        let metadata = Metadata::from_account_info(&ctx.accounts.metadata_info.to_account_info())?;
-        let mut candy_flag = false;
-        let mut reward_type = 0;
-        msg!("Checking create");
-        if let Some(cre) = metadata.data.creators {
-            for c in cre {
-                for i in 0..ctx.accounts.vault.candy_machines.len() {
-                    let candy_machine = ctx.accounts.vault.candy_machines[i];
-                    if c.address == candy_machine {
-                        candy_flag = true;
-                        reward_type = ctx.accounts.vault.reward_types[i];
-                        break;
-                    }
-                }
+/// Whether `user` has at least one NFT staked from every one of `candy_machines`.
+pub fn set_bonus_complete(candy_machines: &[Pubkey], user: &User) -> bool {
+    !candy_machines.is_empty() && candy_machines.iter().all(|candy_machine| {
+        user.candy_machines.iter().any(|x| x == candy_machine)
+    })
+}
 
-                if candy_flag {
-                    break;
-                }
-            }
-        }
-        if candy_flag != true {
-            return Err(ErrorCode::CandyNotMatch.into());
-        }
-        msg!("Passed check candy machine");
-        let user = &mut ctx.accounts.user;
-        let user_store = &mut ctx.accounts.user_store;
-        update_rewards(
-            pool,
-            user,
-            user_store,
-            &mut ctx.accounts.vault,
-            &mut ctx.accounts.cm_reward_per_token,
-        )
-        .unwrap();
-        msg!("updated rewards");
-        user.balance_staked = user.balance_staked.checked_add(1 as u64).unwrap();
-        pool.balance_staked = pool.balance_staked.checked_add(1 as u64).unwrap();
-        msg!("Start nft transfer");
-        // Transfer tokens into the stake vault.
-        {
-            let cpi_ctx = CpiContext::new(
-                ctx.accounts.token_program.to_account_info(),
-                token::Transfer {
-                    from: ctx.accounts.stake_from_account.to_account_info(),
-                    to: ctx.accounts.stake_to_account.to_account_info(),
-                    authority: ctx.accounts.owner.to_account_info(), //todo use user account as signer
-                },
-            );
-            token::transfer(cpi_ctx, 1 as u64)?;
-            msg!("End nft transfer");
-            
-            user_store.nft_mints.push(ctx.accounts.stake_to_account.mint);
-            user_store.types.push(reward_type);
+/// Brings the set bonus and emitted time accumulators up to `current_time`. Must run before the
+/// emission schedule changes, so the time up to the change is weighted by the old schedule.
+pub fn update_set_bonus(pool: &Pool, set_bonus: &mut SetBonus, current_time: u64) -> Result<()> {
+    if current_time <= set_bonus.last_update_time {
+        return Ok(());
+    }
+    let emitted = emitted_time(pool, set_bonus.last_update_time, current_time)?;
+    set_bonus.acc_bonus_bps = set_bonus.acc_bonus_bps.checked_add(
+                                  (set_bonus.bonus_bps as u128).checked_mul(emitted).ok_or(ErrorCode::MathOverflow)?
+                              ).ok_or(ErrorCode::MathOverflow)?;
+    set_bonus.acc_emitted_time = set_bonus.acc_emitted_time.checked_add(emitted).ok_or(ErrorCode::MathOverflow)?;
+    set_bonus.last_update_time = current_time;
 
-            let current_time = clock::Clock::get().unwrap().unix_timestamp.try_into().unwrap();
-            user_store.staked_times.push(current_time);
-        }
+    Ok(())
+}
 
-        Ok(())
+/// Credits `user` with the set bonus earned since its last update. Its candy machines only change right
+/// after an update, so the ones it holds now are the ones it held over the whole interval.
+pub fn update_user_set_bonus(set_bonus: &SetBonus, user: &mut User) -> Result<()> {
+    let mut earned: u128 = 0;
+    let mut from = user.bonus_checkpoint;
+    if user.set_bonus_changes != set_bonus.set_changes {
+        // Only the list before the last change is kept, so across several changes
+        // just the time since the last one counts.
+        if user.set_bonus_changes + 1 == set_bonus.set_changes && set_bonus_complete(&set_bonus.previous_candy_machines, user) {
+            earned = set_bonus.set_changed_acc_bonus_bps.checked_sub(from).ok_or(ErrorCode::MathOverflow)?;
+        }
+        from = set_bonus.set_changed_acc_bonus_bps;
     }
+    if set_bonus_complete(&set_bonus.candy_machines, user) {
+        earned = earned.checked_add(set_bonus.acc_bonus_bps.checked_sub(from).ok_or(ErrorCode::MathOverflow)?)
+                       .ok_or(ErrorCode::MathOverflow)?;
+    }
+    user.earned_bonus_bps = user.earned_bonus_bps.checked_add(earned).ok_or(ErrorCode::MathOverflow)?;
+    user.bonus_checkpoint = set_bonus.acc_bonus_bps;
+    user.set_bonus_changes = set_bonus.set_changes;
 
-    pub fn unstake(ctx: Context<Stake>) -> Result<()> {
-        let pool = &mut ctx.accounts.pool;
-        let user = &mut ctx.accounts.user;
-        let vault = &mut ctx.accounts.vault;
-        let user_store = &mut ctx.accounts.user_store;
-    
-        let metadata = Metadata::from_account_info(&ctx.accounts.metadata_info.to_account_info())?;
-        let mut candy_flag = false;
-
-        if let Some(cre) = metadata.data.creators {
-            for c in cre {
-                for i in 0..vault.candy_machines.len() {
-                    let candy_machine = vault.candy_machines[i];
-                    if c.address == candy_machine {
-                        candy_flag = true;
-                        break;
-                    }
-                }
+    Ok(())
+}
 
-                if candy_flag {
-                    break;
-                }
-            }
-        }
-        if candy_flag != true {
-            return Err(ErrorCode::CandyNotMatch.into());
-        }
+/// Resets the store's reward debts to its current share of each accumulator.
+pub fn sync_reward_debts(
+    user_store: &mut Box<Account<UserStore>>,
+    vault: &Account<Vault>,
+) -> Result<()> {
+    for i in 0..user_store.candy_machines.len() {
+        let candy_machine = user_store.candy_machines[i];
+        let acc_reward_per_share = match vault.candy_machines.iter().position(|&x| x == candy_machine) {
+            Some(index) => vault.acc_reward_per_shares[index],
+            None => 0,
+        };
+        user_store.reward_debts[i] = (user_store.staked_weights[i] as u128).checked_mul(acc_reward_per_share).ok_or(ErrorCode::MathOverflow)?;
+    }
 
-        update_rewards(
-            pool,
-            user,
-            user_store,
-            vault,
-            &mut ctx.accounts.cm_reward_per_token,
-        )
-        .unwrap();
-        user.balance_staked = user.balance_staked.checked_sub(1 as u64).unwrap();
-        pool.balance_staked = pool.balance_staked.checked_sub(1 as u64).unwrap();
+    Ok(())
+}
 
-        // Transfer tokens from the pool vault to user vault.
-        {
-            let seeds = &[
-                pool.to_account_info().key.as_ref(),
-                &[pool.nonce],
-            ];
-            let pool_signer = &[&seeds[..]];
+/// Index of `candy_machine` in the vault if new NFTs can still be staked under it.
+pub fn active_candy_machine(vault: &Vault, candy_machine: &Pubkey) -> Option<usize> {
+    vault.candy_machines.iter().enumerate().position(|(i, x)| x == candy_machine && !vault.retired[i])
+}
 
-            let cpi_ctx = CpiContext::new_with_signer(
-                ctx.accounts.token_program.to_account_info(),
-                token::Transfer {
-                    from: ctx.accounts.stake_to_account.to_account_info(),
-                    to: ctx.accounts.stake_from_account.to_account_info(),
-                    authority: ctx.accounts.pool_signer.to_account_info(),
-                },
-                pool_signer,
-            );
-            token::transfer(cpi_ctx, 1 as u64)?;
+/// Drops the vault entry at `index` once it is removed and its last NFT has left.
+pub fn drop_retired_candy_machine(vault: &mut Vault, index: usize) {
+    if !vault.retired[index] || vault.staked_counts[index] > 0 {
+        return;
+    }
+    vault.candy_machines.remove(index);
+    vault.reward_types.remove(index);
+    vault.acc_reward_per_shares.remove(index);
+    vault.staked_counts.remove(index);
+    vault.early_unstake_penalty_bps.remove(index);
+    vault.staked_weights.remove(index);
+    vault.retired.remove(index);
+}
 
-            let stake_to_account_mint = ctx.accounts.stake_to_account.mint;
+pub fn update_rewards(
+    pool: &mut Account<Pool>,
+    u: &mut Box<Account<User>>,
+    user_store: &mut Box<Account<UserStore>>,
+    vault: &mut Box<Account<Vault>>,
+    cm_reward_per_tokens: &mut Box<Account<CandyMachineRewardPerToken>>,
+    reward_streams: &mut Option<Account<RewardStreams>>,
+    set_bonus: &mut Box<Account<SetBonus>>,
+) -> Result<()> {
+    let current_time = current_timestamp()?;
+    update_reward_pool(pool, vault, cm_reward_per_tokens, current_time)?;
+    if let Some(reward_streams) = reward_streams {
+        update_reward_streams(reward_streams, current_time)?;
+    }
+    update_set_bonus(pool, set_bonus, current_time)?;
+    update_user_set_bonus(set_bonus, u)?;
 
-            let index = user_store.nft_mints.iter().position(|x| *x == stake_to_account_mint).unwrap();
-            user_store.nft_mints.remove(index);
-            user_store.types.remove(index);
-            user_store.staked_times.remove(index);
+    // Every staked NFT earns the same from each stream, whatever its candy machine.
+    let staked_count = user_store.nft_mints.len() as u128;
+    for (i, stream) in reward_streams.iter().flat_map(|r| r.streams.iter()).enumerate() {
+        // Streams added after the store last settled start from a zero accumulator.
+        if user_store.stream_checkpoints.len() <= i {
+            user_store.stream_checkpoints.push(0);
+            user_store.stream_rewards_pending.push(0);
         }
+        let accrued = staked_count.checked_mul(stream.acc_reward_per_share.saturating_sub(user_store.stream_checkpoints[i]))
+                                  .ok_or(ErrorCode::MathOverflow)?;
+        user_store.stream_rewards_pending[i] = user_store.stream_rewards_pending[i].checked_add(accrued).ok_or(ErrorCode::MathOverflow)?;
+        user_store.stream_checkpoints[i] = stream.acc_reward_per_share;
+    }
 
-        Ok(())
+    // Accrued rewards scaled by REWARD_PRECISION and BPS, at the weights each NFT had at the last update.
+    let mut reward_accrued: u128 = 0;
+    for i in 0..user_store.candy_machines.len() {
+        let candy_machine = user_store.candy_machines[i];
+        // Removed candy machines stay in the vault until their last NFT leaves.
+        let acc_reward_per_share = match vault.candy_machines.iter().position(|&x| x == candy_machine) {
+            Some(index) => vault.acc_reward_per_shares[index],
+            None => continue,
+        };
+        let reward_share = (user_store.staked_weights[i] as u128).checked_mul(acc_reward_per_share).ok_or(ErrorCode::MathOverflow)?;
+        reward_accrued = reward_accrued.checked_add(
+                             reward_share.checked_sub(user_store.reward_debts[i]).ok_or(ErrorCode::MathOverflow)?
+                         ).ok_or(ErrorCode::MathOverflow)?;
     }
+    // NFTs that reached a loyalty step since then earn at the step's weight from the step on.
+    let weights_due = nft_weights_due(user_store, vault, current_time);
+    if weights_due {
+        let (gained, lost) = loyalty_step_adjustment(pool, user_store, vault, current_time)?;
+        reward_accrued = reward_accrued.checked_add(gained).ok_or(ErrorCode::MathOverflow)?
+                                       .checked_sub(lost).ok_or(ErrorCode::MathOverflow)?;
+        // The pool accrued those NFTs at their old weight too.
+        pool.reward_liability = pool.reward_liability.checked_add(gained / BPS as u128).ok_or(ErrorCode::MathOverflow)?
+                                                     .saturating_sub(lost / BPS as u128);
+    }
+    // The store earns the bonus its owner averaged over the interval, spread along the emission schedule.
+    // Both sides come from the set bonus accumulators, so a schedule change since can't skew the average.
+    let emitted = set_bonus.acc_emitted_time.checked_sub(user_store.emitted_checkpoint).ok_or(ErrorCode::MathOverflow)?;
+    if emitted > 0 {
+        let bonus_bps = u.earned_bonus_bps.checked_sub(user_store.bonus_checkpoint).ok_or(ErrorCode::MathOverflow)?
+                                          .checked_div(emitted).ok_or(ErrorCode::MathOverflow)?;
+        let bonus = reward_accrued.checked_mul(bonus_bps).ok_or(ErrorCode::MathOverflow)?
+                                  .checked_div(BPS as u128).ok_or(ErrorCode::MathOverflow)?;
+        reward_accrued = reward_accrued.checked_add(bonus).ok_or(ErrorCode::MathOverflow)?;
+        pool.reward_liability = pool.reward_liability.checked_add(bonus.checked_div(BPS as u128).ok_or(ErrorCode::MathOverflow)?)
+                                                     .ok_or(ErrorCode::MathOverflow)?;
+    }
+    user_store.bonus_checkpoint = u.earned_bonus_bps;
+    user_store.emitted_checkpoint = set_bonus.acc_emitted_time;
 
-    pub fn claim(ctx: Context<ClaimReward>) -> Result<()> {
-        let user = &mut ctx.accounts.user;
-        let user_store = &mut ctx.accounts.user_store;
-        update_rewards(
-            &mut ctx.accounts.pool,
-            user,
-            user_store,
-            &mut ctx.accounts.vault,
-            &mut ctx.accounts.cm_reward_per_token,
-        )
-        .unwrap();
+    if current_time >= user_store.next_unlock_time {
+        release_unlocked_nfts(user_store, current_time);
+    }
+    // Their weights move on from here; a changed tier multiplier applies from now on.
+    if weights_due {
+        refresh_nft_weights(user_store, vault, current_time)?;
+    }
+    sync_reward_debts(user_store, vault)?;
+    user_store.withheld = store_withheld(vault, user_store)?;
 
-        let seeds = &[
-            ctx.accounts.pool.to_account_info().key.as_ref(),
-            &[ctx.accounts.pool.nonce],
-        ];
-        let pool_signer = &[&seeds[..]];
+    // Fold in the fraction carried from previous updates and keep the new one.
+    let reward_accrued = reward_accrued.checked_div(BPS as u128).ok_or(ErrorCode::MathOverflow)?
+                                       .checked_add(user_store.reward_remainder).ok_or(ErrorCode::MathOverflow)?;
+    let reward_token_pending: u64 = reward_accrued.checked_div(REWARD_PRECISION).ok_or(ErrorCode::MathOverflow)?
+                                                  .try_into().map_err(|_| ErrorCode::MathOverflow)?;
+    user_store.reward_remainder = reward_accrued.checked_rem(REWARD_PRECISION).ok_or(ErrorCode::MathOverflow)?;
+    user_store.reward_token_pending = user_store.reward_token_pending.checked_add(reward_token_pending).ok_or(ErrorCode::MathOverflow)?;
+    user_store.last_update_time = current_time;
+    u.last_update_time = current_time;
+    
+    Ok(())
+}
 
-        if user_store.reward_token_pending > 0 {
-            let mut reward_amount = user_store.reward_token_pending;
-            let vault_balance = ctx.accounts.reward_vault.amount;
+/// Records the NFT `mint` of rarity `tier` as staked under the vault entry at `vault_index`.
+/// Rewards must already be settled. Returns the time it unlocks.
+#[allow(clippy::too_many_arguments)]
+pub fn add_staked_nft(
+    pool: &mut Account<Pool>,
+    user: &mut Box<Account<User>>,
+    user_store: &mut Box<Account<UserStore>>,
+    vault: &mut Box<Account<Vault>>,
+    vault_index: usize,
+    mint: Pubkey,
+    tier: u8,
+    soft_staked: bool,
+) -> Result<u64> {
+    if user_store.nft_mints.len() >= MAX_NFTS_PER_STORE {
+        return Err(ErrorCode::StoreFull.into());
+    }
+    let reward_type = vault.reward_types[vault_index];
+    let nft_candy_machine = vault.candy_machines[vault_index];
 
-            user_store.reward_token_pending = 0;
-            if vault_balance < reward_amount {
-                reward_amount = vault_balance;
+    user.balance_staked = user.balance_staked.checked_add(1).ok_or(ErrorCode::MathOverflow)?;
+    pool.balance_staked = pool.balance_staked.checked_add(1).ok_or(ErrorCode::MathOverflow)?;
+    match user.candy_machines.iter().position(|&x| x == nft_candy_machine) {
+        Some(index) => {
+            user.staked_counts[index] = user.staked_counts[index].checked_add(1).ok_or(ErrorCode::MathOverflow)?;
+        }
+        None => {
+            if user.candy_machines.len() >= MAX_USER_CANDY_MACHINES {
+                return Err(ErrorCode::TooManyCandyMachines.into());
             }
+            user.candy_machines.push(nft_candy_machine);
+            user.staked_counts.push(1);
+        }
+    }
 
-            if reward_amount > 0 {
-                let cpi_ctx = CpiContext::new_with_signer(
-                    ctx.accounts.token_program.to_account_info(),
-                    token::Transfer {
-                        from: ctx.accounts.reward_vault.to_account_info(),
-                        to: ctx.accounts.reward_account.to_account_info(),
-                        authority: ctx.accounts.pool_signer.to_account_info(),
-                    },
-                    pool_signer,
-                );
-                token::transfer(cpi_ctx, reward_amount)?;
+    let current_time: u64 = current_timestamp()?;
+    // reward_type is the lock period in days, fixed at stake time.
+    let lock_duration = (reward_type as u64).checked_mul(SECONDS_PER_DAY).ok_or(ErrorCode::MathOverflow)?;
+    let unlock_time = current_time.checked_add(lock_duration).ok_or(ErrorCode::MathOverflow)?;
+
+    user_store.nft_mints.push(mint);
+    user_store.nft_candy_machines.push(nft_candy_machine);
+    user_store.types.push(reward_type);
+    user_store.tiers.push(tier);
+    user_store.staked_times.push(current_time);
+    user_store.unlock_times.push(unlock_time);
+    user_store.soft_staked.push(soft_staked);
+    let weight = nft_weight(vault, tier, 0);
+    user_store.nft_weights.push(weight);
+    user_store.entry_reward_per_shares.push(vault.acc_reward_per_shares[vault_index]);
+    user_store.next_weight_update = user_store.next_weight_update.min(next_loyalty_step(vault, current_time, current_time));
+
+    let index = user_store.candy_machines.iter().position(|&x| x == nft_candy_machine);
+    if let Some(index) = index {
+        user_store.staked_counts[index] = user_store.staked_counts[index].checked_add(1).ok_or(ErrorCode::MathOverflow)?;
+        user_store.staked_weights[index] = user_store.staked_weights[index].checked_add(weight).ok_or(ErrorCode::MathOverflow)?;
+    } else {
+        user_store.candy_machines.push(nft_candy_machine);
+        user_store.staked_counts.push(1);
+        user_store.staked_weights.push(weight);
+        user_store.reward_debts.push(0);
+        user_store.locked_weights.push(0);
+        user_store.locked_entry_shares.push(0);
+    }
+    sync_reward_debts(user_store, vault)?;
+    if unlock_time > current_time {
+        let index = user_store.candy_machines.iter().position(|&x| x == nft_candy_machine).ok_or(ErrorCode::NftNotStaked)?;
+        user_store.locked_weights[index] = user_store.locked_weights[index].checked_add(weight).ok_or(ErrorCode::MathOverflow)?;
+        user_store.locked_entry_shares[index] = user_store.locked_entry_shares[index].checked_add(
+                                                    (weight as u128).checked_mul(vault.acc_reward_per_shares[vault_index]).ok_or(ErrorCode::MathOverflow)?
+                                                ).ok_or(ErrorCode::MathOverflow)?;
+        user_store.next_unlock_time = user_store.next_unlock_time.min(unlock_time);
+    }
+
+    vault.staked_counts[vault_index] = vault.staked_counts[vault_index].checked_add(1).ok_or(ErrorCode::MathOverflow)?;
+    vault.staked_weights[vault_index] = vault.staked_weights[vault_index].checked_add(weight).ok_or(ErrorCode::MathOverflow)?;
+
+    Ok(unlock_time)
+}
+
+/// Drops the NFT at `index` from the store and the vault counts.
+/// Rewards must already be settled. Returns the candy machine it was staked under.
+pub fn remove_staked_nft(
+    pool: &mut Account<Pool>,
+    user: &mut Box<Account<User>>,
+    user_store: &mut Box<Account<UserStore>>,
+    vault: &mut Box<Account<Vault>>,
+    index: usize,
+) -> Result<Pubkey> {
+    user.balance_staked = user.balance_staked.checked_sub(1).ok_or(ErrorCode::MathOverflow)?;
+    pool.balance_staked = pool.balance_staked.checked_sub(1).ok_or(ErrorCode::MathOverflow)?;
+
+    let nft_candy_machine = user_store.nft_candy_machines[index];
+    let weight = user_store.nft_weights[index];
+    let user_index = user.candy_machines.iter().position(|&x| x == nft_candy_machine).ok_or(ErrorCode::NftNotStaked)?;
+    user.staked_counts[user_index] = user.staked_counts[user_index].checked_sub(1).ok_or(ErrorCode::MathOverflow)?;
+    if user.staked_counts[user_index] == 0 {
+        user.candy_machines.remove(user_index);
+        user.staked_counts.remove(user_index);
+    }
+    release_locked_nft(user_store, index);
+    user_store.nft_mints.remove(index);
+    user_store.nft_candy_machines.remove(index);
+    user_store.types.remove(index);
+    user_store.tiers.remove(index);
+    user_store.staked_times.remove(index);
+    user_store.unlock_times.remove(index);
+    user_store.soft_staked.remove(index);
+    user_store.nft_weights.remove(index);
+    user_store.entry_reward_per_shares.remove(index);
+
+    let index = user_store.candy_machines.iter().position(|&x| x == nft_candy_machine).ok_or(ErrorCode::NftNotStaked)?;
+    user_store.staked_counts[index] = user_store.staked_counts[index].checked_sub(1).ok_or(ErrorCode::MathOverflow)?;
+    user_store.staked_weights[index] = user_store.staked_weights[index].checked_sub(weight).ok_or(ErrorCode::MathOverflow)?;
+    if user_store.staked_counts[index] == 0 {
+        user_store.candy_machines.remove(index);
+        user_store.staked_counts.remove(index);
+        user_store.staked_weights.remove(index);
+        user_store.reward_debts.remove(index);
+        user_store.locked_weights.remove(index);
+        user_store.locked_entry_shares.remove(index);
+    }
+    sync_reward_debts(user_store, vault)?;
+    user_store.withheld = store_withheld(vault, user_store)?;
+
+    let index = vault.candy_machines.iter().position(|&x| x == nft_candy_machine);
+    if let Some(index) = index {
+        vault.staked_counts[index] = vault.staked_counts[index].checked_sub(1).ok_or(ErrorCode::MathOverflow)?;
+        vault.staked_weights[index] = vault.staked_weights[index].saturating_sub(weight);
+        drop_retired_candy_machine(vault, index);
+    }
+
+    Ok(nft_candy_machine)
+}
+
+/// Drops the NFT at `index` without settling rewards, so it can't fail on reward math.
+/// Only its own candy machine's weight and debt change: the rewards the NFT accrued since the
+/// store was last settled are forfeited, and the rest of the collection keeps its share.
+pub fn remove_unsettled_nft(
+    pool: &mut Account<Pool>,
+    user: &mut Box<Account<User>>,
+    user_store: &mut Box<Account<UserStore>>,
+    vault: &mut Box<Account<Vault>>,
+    index: usize,
+) -> Pubkey {
+    user.balance_staked = user.balance_staked.saturating_sub(1);
+    pool.balance_staked = pool.balance_staked.saturating_sub(1);
+
+    let nft_candy_machine = user_store.nft_candy_machines[index];
+    let weight = user_store.nft_weights[index];
+    if let Some(user_index) = user.candy_machines.iter().position(|&x| x == nft_candy_machine) {
+        user.staked_counts[user_index] = user.staked_counts[user_index].saturating_sub(1);
+        if user.staked_counts[user_index] == 0 {
+            user.candy_machines.remove(user_index);
+            user.staked_counts.remove(user_index);
+        }
+    }
+    release_locked_nft(user_store, index);
+    user_store.nft_mints.remove(index);
+    user_store.nft_candy_machines.remove(index);
+    user_store.types.remove(index);
+    user_store.tiers.remove(index);
+    user_store.staked_times.remove(index);
+    user_store.unlock_times.remove(index);
+    user_store.soft_staked.remove(index);
+    user_store.nft_weights.remove(index);
+    user_store.entry_reward_per_shares.remove(index);
+
+    // Debts are always staked weight times the accumulator at the last settlement.
+    let mut settled_reward_per_share = 0;
+    if let Some(index) = user_store.candy_machines.iter().position(|&x| x == nft_candy_machine) {
+        let staked_weight = user_store.staked_weights[index];
+        if staked_weight > 0 {
+            settled_reward_per_share = user_store.reward_debts[index] / staked_weight as u128;
+        }
+        user_store.staked_counts[index] = user_store.staked_counts[index].saturating_sub(1);
+        user_store.staked_weights[index] = staked_weight.saturating_sub(weight);
+        user_store.reward_debts[index] = settled_reward_per_share.saturating_mul(user_store.staked_weights[index] as u128);
+        if user_store.staked_counts[index] == 0 {
+            user_store.candy_machines.remove(index);
+            user_store.staked_counts.remove(index);
+            user_store.staked_weights.remove(index);
+            user_store.reward_debts.remove(index);
+            user_store.locked_weights.remove(index);
+            user_store.locked_entry_shares.remove(index);
+        }
+    }
+
+    if let Some(index) = vault.candy_machines.iter().position(|&x| x == nft_candy_machine) {
+        vault.staked_counts[index] = vault.staked_counts[index].saturating_sub(1);
+        vault.staked_weights[index] = vault.staked_weights[index].saturating_sub(weight);
+        // What the pool already counted as owed to this NFT will never be paid out.
+        let forfeited = vault.acc_reward_per_shares[index].saturating_sub(settled_reward_per_share)
+                                                          .saturating_mul(weight as u128)
+                                                          / BPS as u128;
+        pool.reward_liability = pool.reward_liability.saturating_sub(forfeited);
+        drop_retired_candy_machine(vault, index);
+    }
+
+    nft_candy_machine
+}
+
+/// Reward tokens unstaking the NFT at `index` at `current_time` would forfeit: `penalty_bps` of what that
+/// NFT accrued since it was staked, at its current weight. Zero once it unlocks or under a hard lock.
+/// The vault accumulators must be up to date.
+pub fn early_unstake_penalty(vault: &Vault, user_store: &UserStore, index: usize, current_time: u64) -> Result<u64> {
+    if current_time >= user_store.unlock_times[index] {
+        return Ok(0);
+    }
+    let vault_index = match vault.candy_machines.iter().position(|&x| x == user_store.nft_candy_machines[index]) {
+        Some(vault_index) => vault_index,
+        None => return Ok(0),
+    };
+    let accrued = vault.acc_reward_per_shares[vault_index].checked_sub(user_store.entry_reward_per_shares[index])
+                                                          .ok_or(ErrorCode::MathOverflow)?
+                                                          .checked_mul(user_store.nft_weights[index] as u128)
+                                                          .ok_or(ErrorCode::MathOverflow)?;
+    let penalty = accrued.checked_mul(vault.early_unstake_penalty_bps[vault_index] as u128).ok_or(ErrorCode::MathOverflow)?
+                         .checked_div((BPS as u128) * (BPS as u128) * REWARD_PRECISION).ok_or(ErrorCode::MathOverflow)?;
+    penalty.try_into().map_err(|_| ErrorCode::MathOverflow.into())
+}
+
+/// Lets the NFT at `index` leave before its unlock time only if its candy machine is soft-locked,
+/// forfeiting the NFT's `early_unstake_penalty` to the reward vault. Rewards must already be settled.
+pub fn check_unstake_lock(
+    pool: &mut Account<Pool>,
+    vault: &Account<Vault>,
+    user_store: &mut Box<Account<UserStore>>,
+    index: usize,
+    current_time: u64,
+) -> Result<()> {
+    if current_time >= user_store.unlock_times[index] {
+        return Ok(());
+    }
+    let candy_machine = user_store.nft_candy_machines[index];
+    let penalty_bps = match vault.candy_machines.iter().position(|&x| x == candy_machine) {
+        Some(vault_index) => vault.early_unstake_penalty_bps[vault_index],
+        None => 0,
+    };
+    if penalty_bps == 0 {
+        return Err(ErrorCode::StakeLocked.into());
+    }
+
+    // claim holds the penalty of every locked NFT back, so it is still pending here.
+    let forfeited = early_unstake_penalty(vault, user_store, index, current_time)?.min(user_store.reward_token_pending);
+    user_store.reward_token_pending = user_store.reward_token_pending.checked_sub(forfeited).ok_or(ErrorCode::MathOverflow)?;
+    // The forfeited tokens never leave the reward vault; they just stop being owed.
+    pool.reward_liability = pool.reward_liability.saturating_sub(
+                                (forfeited as u128).checked_mul(REWARD_PRECISION).ok_or(ErrorCode::MathOverflow)?
+                            );
+
+    emit!(EarlyUnstakePenalty {
+        pool: pool.key(),
+        owner: user_store.owner,
+        store_id: user_store.store_id,
+        mint: user_store.nft_mints[index],
+        forfeited,
+        timestamp: current_time,
+    });
+
+    Ok(())
+}
+
+/// Grows or shrinks the user store account to fit its data, taking the extra rent from `owner`
+/// or refunding the excess to it.
+pub fn fit_user_store<'info>(
+    user_store: &Account<'info, UserStore>,
+    owner: &Signer<'info>,
+    system_program: &Program<'info, System>,
+) -> Result<()> {
+    let info = user_store.to_account_info();
+    let new_len = 8 + user_store.try_to_vec()?.len();
+    if new_len > MAX_USER_STORE_SPACE {
+        return Err(ErrorCode::StoreFull.into());
+    }
+    if new_len == info.data_len() {
+        return Ok(());
+    }
+
+    let rent = Rent::get()?.minimum_balance(new_len);
+    let lamports = info.lamports();
+    if rent > lamports {
+        invoke(
+            &system_instruction::transfer(owner.key, info.key, rent - lamports),
+            &[
+                owner.to_account_info(),
+                info.clone(),
+                system_program.to_account_info(),
+            ],
+        )?;
+    } else if lamports > rent {
+        let refund = lamports - rent;
+        **info.try_borrow_mut_lamports()? -= refund;
+        let owner_info = owner.to_account_info();
+        let owner_lamports = owner_info.lamports().checked_add(refund).ok_or(ErrorCode::MathOverflow)?;
+        **owner_info.try_borrow_mut_lamports()? = owner_lamports;
+    }
+    info.realloc(new_len, false)?;
+
+    Ok(())
+}
+
+/// Records `kind` with `value` and `args` in a new pending action that can run once the pool's timelock delay has passed.
+#[allow(clippy::too_many_arguments)]
+pub fn queue_pending_action(
+    pool: &mut Account<Pool>,
+    pending_action: &mut Account<PendingAction>,
+    proposer: Pubkey,
+    nonce: u8,
+    kind: u8,
+    value: u64,
+    reward_account: Pubkey,
+    args: Vec<u8>,
+) -> Result<()> {
+    let current_time = current_timestamp()?;
+    pending_action.pool = pool.key();
+    pending_action.id = pool.next_action_id;
+    pending_action.proposer = proposer;
+    pending_action.kind = kind;
+    pending_action.value = value;
+    pending_action.reward_account = reward_account;
+    pending_action.args = args.clone();
+    pending_action.eta = current_time.checked_add(pool.timelock_delay).ok_or(ErrorCode::MathOverflow)?;
+    pending_action.nonce = nonce;
+    pool.next_action_id = pool.next_action_id.checked_add(1).ok_or(ErrorCode::MathOverflow)?;
+
+    emit!(ActionQueued {
+        pool: pool.key(),
+        id: pending_action.id,
+        kind,
+        value,
+        args,
+        eta: pending_action.eta,
+        timestamp: current_time,
+    });
+
+    Ok(())
+}
+
+/// Checks that `pending_action` queued `kind` with exactly `args` and that its delay has passed.
+/// Returns the current time.
+pub fn check_queued_call<T: AnchorSerialize>(pending_action: &PendingAction, kind: u8, args: &T) -> Result<u64> {
+    if pending_action.kind == kind && pending_action.args != args.try_to_vec()? {
+        return Err(ErrorCode::ActionArgsMismatch.into());
+    }
+    check_pending_action(pending_action, kind)
+}
+
+/// Checks that `pending_action` is a `kind` action whose delay has passed. Returns the current time.
+pub fn check_pending_action(pending_action: &PendingAction, kind: u8) -> Result<u64> {
+    if pending_action.kind != kind {
+        return Err(ErrorCode::WrongActionKind.into());
+    }
+    let current_time = current_timestamp()?;
+    if current_time < pending_action.eta {
+        return Err(ErrorCode::ActionNotReady.into());
+    }
+
+    emit!(ActionExecuted {
+        pool: pending_action.pool,
+        id: pending_action.id,
+        kind,
+        value: pending_action.value,
+        timestamp: current_time,
+    });
+
+    Ok(current_time)
+}
+
+/// Loads the pool's reward streams, or None for a pool created before reward streams.
+pub fn load_reward_streams<'info>(reward_streams: &AccountInfo<'info>) -> Result<Option<Account<'info, RewardStreams>>> {
+    // The PDA stays a system account until `create_reward_streams` runs.
+    if *reward_streams.owner != ID {
+        return Ok(None);
+    }
+    Ok(Some(Account::try_from(reward_streams)?))
+}
+
+/// Writes back reward streams loaded with `load_reward_streams`.
+pub fn save_reward_streams(reward_streams: &Option<Account<RewardStreams>>) -> Result<()> {
+    if let Some(reward_streams) = reward_streams {
+        reward_streams.exit(&ID)?;
+    }
+    Ok(())
+}
+
+/// Splits `remaining_accounts` into the per-NFT account groups of `stake_many`/`unstake_many`.
+pub fn stake_account_groups<'a, 'info>(accounts: &'a [AccountInfo<'info>]) -> Result<std::slice::ChunksExact<'a, AccountInfo<'info>>> {
+    let groups = accounts.chunks_exact(STAKE_ACCOUNT_GROUP_LEN);
+    if accounts.is_empty() || !groups.remainder().is_empty() {
+        return Err(ErrorCode::InvalidRemainingAccounts.into());
+    }
+    Ok(groups)
+}
+
+/// Applies the `Stake` account checks to one (stake_from_account, stake_to_account,
+/// nft_mint, metadata_info) group: the owner's and the pool signer's token accounts
+/// for the mint, and the mint's metadata PDA. Returns both token accounts and the NFT mint.
+pub fn load_stake_accounts<'info>(
+    group: &[AccountInfo<'info>],
+    owner: &Pubkey,
+    pool_signer: &Pubkey,
+) -> Result<(Account<'info, TokenAccount>, Account<'info, TokenAccount>, Pubkey)> {
+    let stake_from_account: Account<TokenAccount> = Account::try_from(&group[0])?;
+    let stake_to_account: Account<TokenAccount> = Account::try_from(&group[1])?;
+    let nft_mint: Account<Mint> = Account::try_from(&group[2])?;
+    let metadata_info = &group[3];
+
+    if !group[0].is_writable || !group[1].is_writable {
+        return Err(ErrorCode::InvalidRemainingAccounts.into());
+    }
+    if stake_from_account.owner != *owner || stake_to_account.owner != *pool_signer {
+        return Err(ErrorCode::InvalidRemainingAccounts.into());
+    }
+    if nft_mint.supply != 1 || nft_mint.decimals != 0
+        || stake_from_account.mint != nft_mint.key()
+        || stake_to_account.mint != nft_mint.key() {
+        return Err(ErrorCode::InvalidNftMint.into());
+    }
+    let (metadata_key, _) = Pubkey::find_program_address(
+        &[
+            metaplex_token_metadata::state::PREFIX.as_bytes(),
+            metaplex_token_metadata::id().as_ref(),
+            nft_mint.key().as_ref(),
+        ],
+        &metaplex_token_metadata::id(),
+    );
+    if metadata_info.key() != metadata_key || *metadata_info.owner != metaplex_token_metadata::id() {
+        return Err(ErrorCode::InvalidRemainingAccounts.into());
+    }
+
+    Ok((stake_from_account, stake_to_account, nft_mint.key()))
+}
+
+/// Freezes or thaws an NFT held in its owner's wallet through the token metadata
+/// program, with the pool signer acting as the token account's delegate.
+#[allow(clippy::too_many_arguments)]
+pub fn freeze_delegated_nft<'info>(
+    instruction: u8,
+    pool_signer: &UncheckedAccount<'info>,
+    token_account: &Account<'info, TokenAccount>,
+    edition: &UncheckedAccount<'info>,
+    mint: &Account<'info, Mint>,
+    token_program: &Program<'info, Token>,
+    token_metadata_program: &UncheckedAccount<'info>,
+    signer_seeds: &[&[&[u8]]],
+) -> Result<()> {
+    let ix = Instruction {
+        program_id: metaplex_token_metadata::id(),
+        accounts: vec![
+            AccountMeta::new_readonly(pool_signer.key(), true),
+            AccountMeta::new(token_account.key(), false),
+            AccountMeta::new_readonly(edition.key(), false),
+            AccountMeta::new_readonly(mint.key(), false),
+            AccountMeta::new_readonly(token_program.key(), false),
+        ],
+        data: vec![instruction],
+    };
+    invoke_signed(
+        &ix,
+        &[
+            pool_signer.to_account_info(),
+            token_account.to_account_info(),
+            edition.to_account_info(),
+            mint.to_account_info(),
+            token_program.to_account_info(),
+            token_metadata_program.to_account_info(),
+        ],
+        signer_seeds,
+    )?;
+
+    Ok(())
+}
+
+/// Verified collection of an NFT, read from the fields newer token-metadata
+/// versions append after `edition_nonce`.
+pub fn metadata_collection(metadata_info: &AccountInfo, metadata: &Metadata) -> Result<Option<Pubkey>> {
+    let data = metadata_info.try_borrow_data()?;
+    let mut offset = metadata.try_to_vec()?.len();
+
+    // token_standard: Option<TokenStandard>
+    match data.get(offset) {
+        Some(1) => offset += 2,
+        Some(0) => offset += 1,
+        _ => return Ok(None),
+    }
+    // collection: Option<Collection { verified: bool, key: Pubkey }>
+    if data.get(offset) != Some(&1) || data.len() < offset + 34 {
+        return Ok(None);
+    }
+    let verified = data[offset + 1] == 1;
+    if !verified {
+        return Ok(None);
+    }
+    Ok(Some(Pubkey::new(&data[offset + 2..offset + 34])))
+}
+
+/// Index of the vault entry the NFT qualifies under, either as a verified
+/// creator (candy machine) or as its verified collection.
+pub fn find_candy_machine(vault: &Vault, metadata_info: &AccountInfo) -> Result<usize> {
+    let metadata = Metadata::from_account_info(metadata_info)?;
+
+    if let Some(creators) = &metadata.data.creators {
+        for c in creators.iter().filter(|c| c.verified) {
+            if let Some(index) = active_candy_machine(vault, &c.address) {
+                return Ok(index);
             }
         }
+    }
+
+    if let Some(collection) = metadata_collection(metadata_info, &metadata)? {
+        if let Some(index) = active_candy_machine(vault, &collection) {
+            return Ok(index);
+        }
+    }
+
+    Err(ErrorCode::CandyNotMatch.into())
+}
+
+#[program]
+pub mod j_nft_staking {
+    use super::*;
+
+    pub fn initialize_pool(
+        ctx: Context<InitializePool>,
+        pool_nonce: u8,
+        vault_nonce: u8,
+        timelock_delay: u64,
+    ) -> Result<()> {
+        let pool = &mut ctx.accounts.pool;
+
+        pool.authority = ctx.accounts.authority.key();
+        pool.nonce = pool_nonce;
+        pool.pause_flags = 0;
+        pool.reward_mint = ctx.accounts.reward_mint.key();
+        pool.reward_vault = ctx.accounts.reward_vault.key();
+        pool.vault = ctx.accounts.vault.key();
+        pool.reward_per_token = 1_1000_000_000;
+        pool.user_stake_count = 0;
+        pool.balance_staked = 0;
+        pool.reward_liability = 0;
+        // Emit at the flat base rate until a schedule is set.
+        pool.emission_start_time = 0;
+        pool.emission_end_time = u64::MAX;
+        pool.emission_step_interval = 0;
+        pool.emission_step_bps = BPS as u16;
+        pool.rarity_root = [0; 32];
+        pool.pending_authority = Pubkey::default();
+        // Every role starts with the authority until it hands them out with `set_roles`.
+        pool.rate_manager = pool.authority;
+        pool.collection_manager = pool.authority;
+        pool.pauser = pool.authority;
+        pool.treasurer = pool.authority;
+        pool.timelock_delay = timelock_delay;
+        pool.next_action_id = 0;
+
+        let vault = &mut ctx.accounts.vault;
+        vault.nonce = vault_nonce;
+        vault.candy_machines = vec![];
+        vault.reward_types = vec![];
+        vault.acc_reward_per_shares = vec![];
+        vault.staked_counts = vec![];
+        vault.early_unstake_penalty_bps = vec![];
+        vault.staked_weights = vec![];
+        vault.loyalty_days = vec![];
+        vault.loyalty_multiplier_bps = vec![];
+        vault.tier_multiplier_bps = vec![];
+        vault.retired = vec![];
+        vault.last_update_time = current_timestamp()?;
+        vault.multipliers_updated_at = vault.last_update_time;
 
+        emit!(PoolInitialized {
+            pool: ctx.accounts.pool.key(),
+            authority: ctx.accounts.pool.authority,
+            reward_mint: ctx.accounts.pool.reward_mint,
+            timestamp: vault.last_update_time,
+        });
+        
         Ok(())
     }
 
-    pub fn close_user(ctx: Context<CloseUser>) -> Result<()> {
+    /// Nominates `new_authority` to take over the pool. It must sign `accept_authority` to take effect.
+    pub fn propose_authority(ctx: Context<ProposeAuthority>, new_authority: Pubkey) -> Result<()> {
         let pool = &mut ctx.accounts.pool;
-        pool.user_stake_count = pool.user_stake_count.checked_sub(1).unwrap();
+        pool.pending_authority = new_authority;
+
+        emit!(AuthorityProposed {
+            pool: pool.key(),
+            authority: pool.authority,
+            pending_authority: new_authority,
+            timestamp: current_timestamp()?,
+        });
+
         Ok(())
     }
 
-    pub fn close_n_account(ctx: Context<CloseNAccount>) -> Result<()> {
-        let pool = &ctx.accounts.pool;
-        let seeds = &[
-            pool.to_account_info().key.as_ref(),
-            &[pool.nonce],
-        ];
-        let pool_signer = &[&seeds[..]];
-        anchor_spl::token::close_account(
-            CpiContext::new_with_signer(
-                ctx.accounts.token_program.to_account_info(),
-                anchor_spl::token::CloseAccount{
-                    account: ctx.accounts.close_account.to_account_info(),
-                    destination: ctx.accounts.owner.to_account_info(),
-                    authority: ctx.accounts.pool_signer.to_account_info(),
-                },
-                pool_signer
-        ))?;
+    /// Hands the pool to the pending authority, along with every role the old authority still held.
+    pub fn accept_authority(ctx: Context<AcceptAuthority>) -> Result<()> {
+        let pool = &mut ctx.accounts.pool;
+        let old_authority = pool.authority;
+        let new_authority = ctx.accounts.pending_authority.key();
+        pool.authority = new_authority;
+        pool.pending_authority = Pubkey::default();
+        let roles: &mut Pool = pool;
+        for role in [&mut roles.rate_manager, &mut roles.collection_manager, &mut roles.pauser, &mut roles.treasurer] {
+            if *role == old_authority {
+                *role = new_authority;
+            }
+        }
+        let current_time = current_timestamp()?;
+
+        emit!(AuthorityChanged {
+            pool: pool.key(),
+            old_authority,
+            new_authority,
+            timestamp: current_time,
+        });
+        emit!(RolesChanged {
+            pool: pool.key(),
+            rate_manager: pool.rate_manager,
+            collection_manager: pool.collection_manager,
+            pauser: pool.pauser,
+            treasurer: pool.treasurer,
+            timestamp: current_time,
+        });
 
         Ok(())
     }
 
-    pub fn withdraw_reward(ctx: Context<WithdrawReward>, amount: u64) -> Result<()> {
+    pub fn set_roles(
+        ctx: Context<SetRoles>,
+        rate_manager: Pubkey,
+        collection_manager: Pubkey,
+        pauser: Pubkey,
+        treasurer: Pubkey,
+    ) -> Result<()> {
+        let pool = &mut ctx.accounts.pool;
+        pool.rate_manager = rate_manager;
+        pool.collection_manager = collection_manager;
+        pool.pauser = pauser;
+        pool.treasurer = treasurer;
 
-        let seeds = &[
-            ctx.accounts.pool.to_account_info().key.as_ref(),
-            &[ctx.accounts.pool.nonce],
-        ];
-        let pool_signer = &[&seeds[..]];
+        emit!(RolesChanged {
+            pool: pool.key(),
+            rate_manager,
+            collection_manager,
+            pauser,
+            treasurer,
+            timestamp: current_timestamp()?,
+        });
 
-        let mut withdraw_amount = amount;
-        let vault_balance = ctx.accounts.reward_vault.amount;
+        Ok(())
+    }
 
-        if vault_balance < withdraw_amount {
-            withdraw_amount = vault_balance;
+    /// Queues a change of the pool rate, which anyone can apply with `execute_set_reward_per_token`
+    /// once the timelock delay has passed.
+    pub fn queue_set_reward_per_token(ctx: Context<QueueAction>, nonce: u8, reward_per_token: u64) -> Result<()> {
+        if ctx.accounts.pool.rate_manager != ctx.accounts.authority.key() {
+            return Err(ErrorCode::Unauthorized.into());
         }
+        let proposer = ctx.accounts.authority.key();
+        queue_pending_action(
+            &mut ctx.accounts.pool,
+            &mut ctx.accounts.pending_action,
+            proposer,
+            nonce,
+            ACTION_SET_REWARD_PER_TOKEN,
+            reward_per_token,
+            Pubkey::default(),
+            vec![],
+        )
+    }
 
-        if withdraw_amount > 0 {
-            let cpi_ctx = CpiContext::new_with_signer(
-                ctx.accounts.token_program.to_account_info(),
-                token::Transfer {
-                    from: ctx.accounts.reward_vault.to_account_info(),
-                    to: ctx.accounts.reward_account.to_account_info(),
-                    authority: ctx.accounts.pool_signer.to_account_info(),
-                },
-                pool_signer,
-            );
-            token::transfer(cpi_ctx, withdraw_amount)?;
+    /// Queues a withdrawal from the reward vault to `reward_account`, which anyone can run with
+    /// `execute_withdraw_reward` once the timelock delay has passed.
+    pub fn queue_withdraw_reward(ctx: Context<QueueAction>, nonce: u8, amount: u64, reward_account: Pubkey) -> Result<()> {
+        if ctx.accounts.pool.treasurer != ctx.accounts.authority.key() {
+            return Err(ErrorCode::Unauthorized.into());
         }
+        let proposer = ctx.accounts.authority.key();
+        queue_pending_action(
+            &mut ctx.accounts.pool,
+            &mut ctx.accounts.pending_action,
+            proposer,
+            nonce,
+            ACTION_WITHDRAW_REWARD,
+            amount,
+            reward_account,
+            vec![],
+        )
+    }
 
-        Ok(())
+    /// Queues a shorter timelock delay. Longer delays apply at once through `set_timelock_delay`.
+    pub fn queue_set_timelock_delay(ctx: Context<QueueAction>, nonce: u8, timelock_delay: u64) -> Result<()> {
+        if ctx.accounts.pool.authority != ctx.accounts.authority.key() {
+            return Err(ErrorCode::Unauthorized.into());
+        }
+        let proposer = ctx.accounts.authority.key();
+        queue_pending_action(
+            &mut ctx.accounts.pool,
+            &mut ctx.accounts.pending_action,
+            proposer,
+            nonce,
+            ACTION_SET_TIMELOCK_DELAY,
+            timelock_delay,
+            Pubkey::default(),
+            vec![],
+        )
     }
 
-    pub fn deposit_reward(ctx: Context<DepositReward>, amount: u64) -> Result<()> {
-        let depositor_balance = ctx.accounts.reward_depositor.amount;
-        let mut deposit_amount = amount;
-        if amount > depositor_balance {
-            deposit_amount = depositor_balance;
+    /// Queues a rate change. `args` are the Borsh-encoded arguments of the instruction `kind` gates,
+    /// which its role can call with exactly those arguments once the timelock delay has passed.
+    pub fn queue_action(ctx: Context<QueueAction>, nonce: u8, kind: u8, args: Vec<u8>) -> Result<()> {
+        let role = match kind {
+            ACTION_SET_EMISSION_SCHEDULE..=ACTION_SET_SET_BONUS | ACTION_SET_RARITY_ROOT => ctx.accounts.pool.rate_manager,
+            ACTION_SET_EARLY_UNSTAKE_PENALTY | ACTION_REMOVE_CANDY_MACHINE => ctx.accounts.pool.collection_manager,
+            _ => return Err(ErrorCode::WrongActionKind.into()),
+        };
+        if role != ctx.accounts.authority.key() {
+            return Err(ErrorCode::Unauthorized.into());
         }
-        //lp lockup
-        let cpi_ctx = CpiContext::new(
-            ctx.accounts.token_program.to_account_info(),
-            token::Transfer {
-                from: ctx.accounts.reward_depositor.to_account_info(),
-                to: ctx.accounts.reward_vault.to_account_info(),
-                authority: ctx.accounts.reward_deposit_authority.to_account_info(),
-            },
-        );
-        token::transfer(cpi_ctx, deposit_amount)?;
-        
-        Ok(())
+        if args.len() > MAX_ACTION_ARGS {
+            return Err(ErrorCode::ActionArgsMismatch.into());
+        }
+        let proposer = ctx.accounts.authority.key();
+        queue_pending_action(
+            &mut ctx.accounts.pool,
+            &mut ctx.accounts.pending_action,
+            proposer,
+            nonce,
+            kind,
+            0,
+            Pubkey::default(),
+            args,
+        )
     }
 
+    /// Lengthens the timelock delay immediately.
+    pub fn set_timelock_delay(ctx: Context<SetTimelockDelay>, timelock_delay: u64) -> Result<()> {
+        let pool = &mut ctx.accounts.pool;
+        if timelock_delay < pool.timelock_delay {
+            return Err(ErrorCode::TimelockDecrease.into());
+        }
+        pool.timelock_delay = timelock_delay;
+
+        emit!(TimelockDelayChanged {
+            pool: pool.key(),
+            timelock_delay,
+            timestamp: current_timestamp()?,
+        });
+
+        Ok(())
+    }
+
+    pub fn execute_set_timelock_delay(ctx: Context<ExecuteSetTimelockDelay>) -> Result<()> {
+        let current_time = check_pending_action(&ctx.accounts.pending_action, ACTION_SET_TIMELOCK_DELAY)?;
+        let pool = &mut ctx.accounts.pool;
+        pool.timelock_delay = ctx.accounts.pending_action.value;
+
+        emit!(TimelockDelayChanged {
+            pool: pool.key(),
+            timelock_delay: pool.timelock_delay,
+            timestamp: current_time,
+        });
+
+        Ok(())
+    }
+
+    /// Drops a queued action. Either the authority or whoever queued it can cancel.
+    pub fn cancel_pending_action(ctx: Context<CancelPendingAction>) -> Result<()> {
+        emit!(ActionCancelled {
+            pool: ctx.accounts.pool.key(),
+            id: ctx.accounts.pending_action.id,
+            kind: ctx.accounts.pending_action.kind,
+            timestamp: current_timestamp()?,
+        });
+
+        Ok(())
+    }
+
+    pub fn execute_set_reward_per_token(ctx: Context<ExecuteSetRewardPerToken>) -> Result<()> {
+        let current_time = check_pending_action(&ctx.accounts.pending_action, ACTION_SET_REWARD_PER_TOKEN)?;
+        let reward_per_token = ctx.accounts.pending_action.value;
+        let pool = &mut ctx.accounts.pool;
+        // Price the time so far at the old rate before switching.
+        update_reward_pool(pool, &mut ctx.accounts.vault, &ctx.accounts.cm_reward_per_token, current_time)?;
+        pool.reward_per_token = reward_per_token;
+
+        emit!(RewardRateChanged {
+            pool: pool.key(),
+            candy_machine: None,
+            reward_per_token,
+            timestamp: current_time,
+        });
+
+        Ok(())
+    }
+
+    /// Emits rewards only between `start_time` and `end_time`, scaling the base rates by
+    /// `step_bps` every `step_interval` seconds (0 for a flat rate). Accrual up to now is kept.
+    /// Must be queued with `queue_action` first.
+    pub fn set_emission_schedule(
+        ctx: Context<SetEmissionSchedule>,
+        start_time: u64,
+        end_time: u64,
+        step_interval: u64,
+        step_bps: u16,
+    ) -> Result<()> {
+        if start_time > end_time || step_bps as u64 > BPS {
+            return Err(ErrorCode::InvalidEmissionSchedule.into());
+        }
+        // Bound the steps accrual has to walk. A flat schedule has no steps.
+        if step_interval > 0 && (step_bps as u64) < BPS
+            && (end_time - start_time) / step_interval > MAX_EMISSION_STEPS {
+            return Err(ErrorCode::InvalidEmissionSchedule.into());
+        }
+        let current_time = check_queued_call(
+            &ctx.accounts.pending_action,
+            ACTION_SET_EMISSION_SCHEDULE,
+            &(start_time, end_time, step_interval, step_bps),
+        )?;
+        let pool = &mut ctx.accounts.pool;
+        update_reward_pool(pool, &mut ctx.accounts.vault, &ctx.accounts.cm_reward_per_token, current_time)?;
+        update_set_bonus(pool, &mut ctx.accounts.set_bonus, current_time)?;
+
+        pool.emission_start_time = start_time;
+        pool.emission_end_time = end_time;
+        pool.emission_step_interval = step_interval;
+        pool.emission_step_bps = step_bps;
+
+        emit!(EmissionScheduleChanged {
+            pool: pool.key(),
+            start_time,
+            end_time,
+            step_interval,
+            step_bps,
+            timestamp: current_time,
+        });
+
+        Ok(())
+    }
+
+    /// Publishes the Merkle root of (mint, tier) pairs that `stake` proofs are checked against.
+    /// NFTs already staked keep their tier.
+    /// Must be queued with `queue_action` first.
+    pub fn set_rarity_root(ctx: Context<SetRarityRoot>, rarity_root: [u8; 32]) -> Result<()> {
+        let current_time = check_queued_call(&ctx.accounts.pending_action, ACTION_SET_RARITY_ROOT, &rarity_root)?;
+        let pool = &mut ctx.accounts.pool;
+        pool.rarity_root = rarity_root;
+
+        emit!(RarityRootChanged {
+            pool: pool.key(),
+            rarity_root,
+            timestamp: current_time,
+        });
+
+        Ok(())
+    }
+
+    pub fn create_candy_machine_reward_per_token(ctx: Context<CreateCandyMachineRewardPerToken>, nonce: u8) -> Result<()> {
+        let cm_reward_per_token = &mut ctx.accounts.cm_reward_per_token;
+        cm_reward_per_token.nonce = nonce;
+        cm_reward_per_token.candy_machines = vec![];
+        cm_reward_per_token.reward_per_tokens = vec![];
+
+        emit!(CandyMachineRewardPerTokenCreated {
+            pool: ctx.accounts.pool.key(),
+            authority: ctx.accounts.authority.key(),
+            timestamp: current_timestamp()?,
+        });
+
+        Ok(())
+    }
+
+    /// Gives `candy_machine` its own rate. Must be queued with `queue_action` first.
+    pub fn set_candy_machine_reward_per_token(ctx: Context<SetCandyMachineRewardPerToken>, candy_machine: Pubkey, reward_per_token: u64) -> Result<()> {
+        let current_time = check_queued_call(
+            &ctx.accounts.pending_action,
+            ACTION_SET_CANDY_MACHINE_REWARD_PER_TOKEN,
+            &(candy_machine, reward_per_token),
+        )?;
+        update_reward_pool(&mut ctx.accounts.pool, &mut ctx.accounts.vault, &ctx.accounts.cm_reward_per_token, current_time)?;
+        let cm_reward_per_token = &mut ctx.accounts.cm_reward_per_token;
+        let index = cm_reward_per_token.candy_machines.iter().position(|&x| x == candy_machine);
+        if let Some(index) = index {
+            cm_reward_per_token.reward_per_tokens[index] = reward_per_token;
+        } else {
+            cm_reward_per_token.candy_machines.push(candy_machine);
+            cm_reward_per_token.reward_per_tokens.push(reward_per_token);
+        }
+
+        emit!(RewardRateChanged {
+            pool: ctx.accounts.pool.key(),
+            candy_machine: Some(candy_machine),
+            reward_per_token,
+            timestamp: current_time,
+        });
+
+        Ok(())
+    }
+
+    /// Returns `candy_machine` to the pool rate. Must be queued with `queue_action` first.
+    pub fn remove_candy_machine_reward_per_token(ctx: Context<SetCandyMachineRewardPerToken>, candy_machine: Pubkey) -> Result<()> {
+        let current_time = check_queued_call(
+            &ctx.accounts.pending_action,
+            ACTION_REMOVE_CANDY_MACHINE_REWARD_PER_TOKEN,
+            &candy_machine,
+        )?;
+        update_reward_pool(&mut ctx.accounts.pool, &mut ctx.accounts.vault, &ctx.accounts.cm_reward_per_token, current_time)?;
+        let cm_reward_per_token = &mut ctx.accounts.cm_reward_per_token;
+        let index = cm_reward_per_token.candy_machines.iter().position(|&x| x == candy_machine);
+        if let Some(index) = index {
+            cm_reward_per_token.candy_machines.remove(index);
+            cm_reward_per_token.reward_per_tokens.remove(index);
+        }
+
+        // The candy machine falls back to the pool rate.
+        emit!(RewardRateChanged {
+            pool: ctx.accounts.pool.key(),
+            candy_machine: Some(candy_machine),
+            reward_per_token: ctx.accounts.pool.reward_per_token,
+            timestamp: current_time,
+        });
+
+        Ok(())
+    }
+
+    pub fn create_reward_streams(ctx: Context<CreateRewardStreams>, nonce: u8) -> Result<()> {
+        let reward_streams = &mut ctx.accounts.reward_streams;
+        reward_streams.nonce = nonce;
+        reward_streams.streams = vec![];
+
+        emit!(RewardStreamsCreated {
+            pool: ctx.accounts.pool.key(),
+            authority: ctx.accounts.authority.key(),
+            timestamp: current_timestamp()?,
+        });
+
+        Ok(())
+    }
+
+    /// Adds a reward stream paying `reward_per_token` per staked NFT per day between `start_time` and `end_time`.
+    pub fn add_reward_stream(ctx: Context<AddRewardStream>, reward_per_token: u64, start_time: u64, end_time: u64) -> Result<()> {
+        if start_time > end_time {
+            return Err(ErrorCode::InvalidTimestamp.into());
+        }
+        let reward_streams = &mut ctx.accounts.reward_streams;
+        if reward_streams.streams.len() >= MAX_REWARD_STREAMS {
+            return Err(ErrorCode::TooManyRewardStreams.into());
+        }
+        let current_time = current_timestamp()?;
+        reward_streams.streams.push(RewardStream {
+            reward_mint: ctx.accounts.reward_mint.key(),
+            reward_vault: ctx.accounts.reward_vault.key(),
+            reward_per_token,
+            start_time,
+            end_time,
+            acc_reward_per_share: 0,
+            last_update_time: current_time,
+        });
+
+        emit!(RewardStreamChanged {
+            pool: ctx.accounts.pool.key(),
+            index: (reward_streams.streams.len() - 1) as u8,
+            reward_mint: ctx.accounts.reward_mint.key(),
+            reward_per_token,
+            start_time,
+            end_time,
+            timestamp: current_time,
+        });
+
+        Ok(())
+    }
+
+    /// Changes the rate and window of the stream at `index`. Accrual up to now is kept at the old rate.
+    /// Must be queued with `queue_action` first.
+    pub fn set_reward_stream(ctx: Context<SetRewardStream>, index: u8, reward_per_token: u64, start_time: u64, end_time: u64) -> Result<()> {
+        if start_time > end_time {
+            return Err(ErrorCode::InvalidTimestamp.into());
+        }
+        let current_time = check_queued_call(
+            &ctx.accounts.pending_action,
+            ACTION_SET_REWARD_STREAM,
+            &(index, reward_per_token, start_time, end_time),
+        )?;
+        let reward_streams = &mut ctx.accounts.reward_streams;
+        update_reward_streams(reward_streams, current_time)?;
+
+        let stream = reward_streams.streams.get_mut(index as usize).ok_or(ErrorCode::RewardStreamNotFound)?;
+        stream.reward_per_token = reward_per_token;
+        stream.start_time = start_time;
+        stream.end_time = end_time;
+
+        emit!(RewardStreamChanged {
+            pool: ctx.accounts.pool.key(),
+            index,
+            reward_mint: stream.reward_mint,
+            reward_per_token,
+            start_time,
+            end_time,
+            timestamp: current_time,
+        });
+
+        Ok(())
+    }
+
+    pub fn create_set_bonus(ctx: Context<CreateSetBonus>, nonce: u8) -> Result<()> {
+        let set_bonus = &mut ctx.accounts.set_bonus;
+        set_bonus.nonce = nonce;
+        set_bonus.candy_machines = vec![];
+        set_bonus.bonus_bps = 0;
+        set_bonus.acc_bonus_bps = 0;
+        set_bonus.last_update_time = current_timestamp()?;
+        set_bonus.previous_candy_machines = vec![];
+        set_bonus.set_changed_acc_bonus_bps = 0;
+        set_bonus.set_changes = 0;
+        set_bonus.acc_emitted_time = 0;
+
+        emit!(SetBonusCreated {
+            pool: ctx.accounts.pool.key(),
+            authority: ctx.accounts.authority.key(),
+            timestamp: set_bonus.last_update_time,
+        });
+
+        Ok(())
+    }
+
+    /// Pays `bonus_bps` extra on the rewards of users with at least one NFT staked from each of
+    /// `candy_machines`. An empty set turns the bonus off. The bonus earned so far is kept.
+    /// Must be queued with `queue_action` first.
+    pub fn set_set_bonus(ctx: Context<SetSetBonus>, candy_machines: Vec<Pubkey>, bonus_bps: u16) -> Result<()> {
+        if candy_machines.len() > MAX_SET_BONUS_CANDY_MACHINES {
+            return Err(ErrorCode::TooManyCandyMachines.into());
+        }
+        let current_time = check_queued_call(
+            &ctx.accounts.pending_action,
+            ACTION_SET_SET_BONUS,
+            &(candy_machines.clone(), bonus_bps),
+        )?;
+        let set_bonus = &mut ctx.accounts.set_bonus;
+        update_set_bonus(&ctx.accounts.pool, set_bonus, current_time)?;
+        if candy_machines != set_bonus.candy_machines {
+            // Users settle against the previous list up to this point.
+            set_bonus.previous_candy_machines = std::mem::replace(&mut set_bonus.candy_machines, candy_machines.clone());
+            set_bonus.set_changed_acc_bonus_bps = set_bonus.acc_bonus_bps;
+            set_bonus.set_changes = set_bonus.set_changes.checked_add(1).ok_or(ErrorCode::MathOverflow)?;
+        }
+        set_bonus.bonus_bps = bonus_bps;
+
+        emit!(SetBonusChanged {
+            pool: ctx.accounts.pool.key(),
+            candy_machines,
+            bonus_bps,
+            timestamp: current_time,
+        });
+
+        Ok(())
+    }
+
+    pub fn create_user(ctx: Context<CreateUser>, nonce: u8, store_nonce: u8) -> Result<()> {
+        let user = &mut ctx.accounts.user;
+        user.pool = *ctx.accounts.pool.to_account_info().key;
+        user.owner = *ctx.accounts.owner.key;
+        user.balance_staked = 0;
+        user.stores = 1;
+        user.last_store_id = 1;
+        user.candy_machines = vec![];
+        user.staked_counts = vec![];
+        user.bonus_checkpoint = 0;
+        user.set_bonus_changes = 0;
+        user.earned_bonus_bps = 0;
+
+        let current_time = current_timestamp()?;
+
+        user.last_update_time = current_time;
+        user.nonce = nonce;
+
+        let user_store = &mut ctx.accounts.user_store;
+        user_store.nft_mints = vec![];
+        user_store.nft_candy_machines = vec![];
+        user_store.types = vec![];
+        user_store.tiers = vec![];
+        user_store.staked_times = vec![];
+        user_store.unlock_times = vec![];
+        user_store.soft_staked = vec![];
+        user_store.candy_machines = vec![];
+        user_store.nft_weights = vec![];
+        user_store.staked_counts = vec![];
+        user_store.staked_weights = vec![];
+        user_store.reward_debts = vec![];
+        user_store.stream_checkpoints = vec![];
+        user_store.stream_rewards_pending = vec![];
+        user_store.entry_reward_per_shares = vec![];
+        user_store.locked_weights = vec![];
+        user_store.locked_entry_shares = vec![];
+        user_store.owner = *ctx.accounts.owner.key;
+        user_store.nonce = store_nonce;
+        user_store.store_id = user.last_store_id;
+        user_store.reward_token_pending = 0;
+        user_store.reward_remainder = 0;
+        user_store.last_update_time = current_time;
+        user_store.next_weight_update = u64::MAX;
+        user_store.next_unlock_time = u64::MAX;
+        user_store.withheld = 0;
+        user_store.bonus_checkpoint = user.earned_bonus_bps;
+        // Set on the first stake; an empty store earns no bonus before that.
+        user_store.emitted_checkpoint = 0;
+
+
+        let pool = &mut ctx.accounts.pool;
+        pool.user_stake_count = pool.user_stake_count.checked_add(1).ok_or(ErrorCode::MathOverflow)?;
+
+        emit!(UserCreated {
+            pool: pool.key(),
+            owner: user.owner,
+            timestamp: current_time,
+        });
+
+        Ok(())
+    }
+
+    pub fn create_user_store(ctx: Context<CreateUserStore>, nonce: u8) -> Result<()> {
+        let user = &mut ctx.accounts.user;
+        user.stores = user.stores.checked_add(1).ok_or(ErrorCode::MathOverflow)?;
+        user.last_store_id = user.last_store_id.checked_add(1).ok_or(ErrorCode::StoreFull)?;
+
+        let user_store = &mut ctx.accounts.user_store;
+        user_store.nft_mints = vec![];
+        user_store.nft_candy_machines = vec![];
+        user_store.types = vec![];
+        user_store.tiers = vec![];
+        user_store.staked_times = vec![];
+        user_store.unlock_times = vec![];
+        user_store.soft_staked = vec![];
+        user_store.candy_machines = vec![];
+        user_store.nft_weights = vec![];
+        user_store.staked_counts = vec![];
+        user_store.staked_weights = vec![];
+        user_store.reward_debts = vec![];
+        user_store.stream_checkpoints = vec![];
+        user_store.stream_rewards_pending = vec![];
+        user_store.entry_reward_per_shares = vec![];
+        user_store.locked_weights = vec![];
+        user_store.locked_entry_shares = vec![];
+        user_store.owner = *ctx.accounts.owner.key;
+        user_store.nonce = nonce;
+        user_store.store_id = user.last_store_id;
+        user_store.reward_token_pending = 0;
+        user_store.reward_remainder = 0;
+        user_store.last_update_time = current_timestamp()?;
+        user_store.next_weight_update = u64::MAX;
+        user_store.next_unlock_time = u64::MAX;
+        user_store.withheld = 0;
+        user_store.bonus_checkpoint = user.earned_bonus_bps;
+        // Set on the first stake; an empty store earns no bonus before that.
+        user_store.emitted_checkpoint = 0;
+
+        emit!(UserStoreCreated {
+            pool: ctx.accounts.pool.key(),
+            owner: user_store.owner,
+            store_id: user_store.store_id,
+            timestamp: user_store.last_update_time,
+        });
+
+        Ok(())
+    }
+
+    /// Pauses the flows in `flags`: new stakes, claims and/or admin changes.
+    pub fn pause(ctx: Context<Pause>, flags: u8) -> Result<()> {
+        if flags == 0 || flags & !PAUSE_ALL != 0 {
+            return Err(ErrorCode::InvalidPauseFlags.into());
+        }
+        let pool = &mut ctx.accounts.pool;
+        pool.pause_flags |= flags;
+
+        emit!(Paused {
+            pool: pool.key(),
+            pause_flags: pool.pause_flags,
+            timestamp: current_timestamp()?,
+        });
+
+        Ok(())
+    }
+
+    /// Resumes the flows in `flags`.
+    pub fn unpause(ctx: Context<Unpause>, flags: u8) -> Result<()> {
+        if flags == 0 || flags & !PAUSE_ALL != 0 {
+            return Err(ErrorCode::InvalidPauseFlags.into());
+        }
+        let pool = &mut ctx.accounts.pool;
+        pool.pause_flags &= !flags;
+
+        emit!(Unpaused {
+            pool: pool.key(),
+            pause_flags: pool.pause_flags,
+            timestamp: current_timestamp()?,
+        });
+
+        Ok(())
+    }
+
+    pub fn add_candy_machine(
+                                ctx: Context<ManageCandyMachine>, 
+                                candy_machine: Pubkey, 
+                                reward_type: u8,
+                            ) -> Result<()> {
+        let current_time = current_timestamp()?;
+        update_reward_pool(&mut ctx.accounts.pool, &mut ctx.accounts.vault, &ctx.accounts.cm_reward_per_token, current_time)?;
+        let vault = &mut ctx.accounts.vault;
+        let index = vault.candy_machines.iter().position(|&x| x == candy_machine);
+        if let Some(index) = index {
+            // Adding back a removed candy machine resumes its accumulator where it stopped.
+            vault.reward_types[index] = reward_type;
+            vault.retired[index] = false;
+        } else {
+            vault.candy_machines.push(candy_machine);
+            vault.reward_types.push(reward_type);
+            vault.acc_reward_per_shares.push(0);
+            vault.staked_counts.push(0);
+            vault.early_unstake_penalty_bps.push(0);
+            vault.staked_weights.push(0);
+            vault.retired.push(false);
+        }
+
+        emit!(CandyMachineAdded {
+            pool: ctx.accounts.pool.key(),
+            candy_machine,
+            reward_type,
+            timestamp: current_time,
+        });
+        Ok(())
+    }
+
+    /// Makes NFTs of `candy_machine` soft-locked: they can leave before their unlock time by forfeiting
+    /// `penalty_bps` of what they earned since they were staked. 0 restores the hard lock.
+    /// Must be queued with `queue_action` first.
+    pub fn set_early_unstake_penalty(ctx: Context<SetEarlyUnstakePenalty>, candy_machine: Pubkey, penalty_bps: u16) -> Result<()> {
+        if penalty_bps as u64 > BPS {
+            return Err(ErrorCode::InvalidPenalty.into());
+        }
+        let current_time = check_queued_call(
+            &ctx.accounts.pending_action,
+            ACTION_SET_EARLY_UNSTAKE_PENALTY,
+            &(candy_machine, penalty_bps),
+        )?;
+        let vault = &mut ctx.accounts.vault;
+        let index = active_candy_machine(vault, &candy_machine).ok_or(ErrorCode::CandyNotMatch)?;
+        vault.early_unstake_penalty_bps[index] = penalty_bps;
+
+        emit!(EarlyUnstakePenaltyChanged {
+            pool: ctx.accounts.pool.key(),
+            candy_machine,
+            penalty_bps,
+            timestamp: current_time,
+        });
+        Ok(())
+    }
+
+    /// Sets the loyalty curve: NFTs staked for at least `days[i]` days earn `multiplier_bps[i]` of the
+    /// base rate. Steps must be in ascending order of days. The new curve applies from each store's next update.
+    /// Must be queued with `queue_action` first.
+    pub fn set_loyalty_multipliers(ctx: Context<ManageVaultRates>, days: Vec<u16>, multiplier_bps: Vec<u16>) -> Result<()> {
+        if days.len() != multiplier_bps.len() || days.len() > MAX_LOYALTY_STEPS || days.windows(2).any(|w| w[0] >= w[1]) {
+            return Err(ErrorCode::InvalidLoyaltyMultipliers.into());
+        }
+        let current_time = check_queued_call(
+            &ctx.accounts.pending_action,
+            ACTION_SET_LOYALTY_MULTIPLIERS,
+            &(days.clone(), multiplier_bps.clone()),
+        )?;
+        let vault = &mut ctx.accounts.vault;
+        vault.loyalty_days = days.clone();
+        vault.loyalty_multiplier_bps = multiplier_bps.clone();
+        vault.multipliers_updated_at = current_time;
+
+        emit!(LoyaltyMultipliersChanged {
+            pool: ctx.accounts.pool.key(),
+            days,
+            multiplier_bps,
+            timestamp: current_time,
+        });
+        Ok(())
+    }
+
+    /// Sets the reward multiplier of each rarity tier in basis points, indexed by tier.
+    /// Tiers past the end earn 1x. Staked NFTs move to the new multipliers at their next update.
+    /// Must be queued with `queue_action` first.
+    pub fn set_tier_multipliers(ctx: Context<ManageVaultRates>, multiplier_bps: Vec<u16>) -> Result<()> {
+        if multiplier_bps.len() > MAX_RARITY_TIERS {
+            return Err(ErrorCode::InvalidTierMultipliers.into());
+        }
+        let current_time = check_queued_call(&ctx.accounts.pending_action, ACTION_SET_TIER_MULTIPLIERS, &multiplier_bps)?;
+        let vault = &mut ctx.accounts.vault;
+        vault.tier_multiplier_bps = multiplier_bps.clone();
+        vault.multipliers_updated_at = current_time;
+
+        emit!(TierMultipliersChanged {
+            pool: ctx.accounts.pool.key(),
+            multiplier_bps,
+            timestamp: current_time,
+        });
+        Ok(())
+    }
+
+    /// Stops new stakes and further accrual for `candy_machine`. Its accumulator is kept at its final
+    /// value until the last of its NFTs is unstaked, so their owners can still settle what they earned.
+    /// Stops new stakes under `candy_machine`; its staked NFTs stop earning but can still leave.
+    /// Must be queued with `queue_action` first.
+    pub fn remove_candy_machine(ctx: Context<RemoveCandyMachine>, 
+                                candy_machine: Pubkey, ) -> Result<()> {
+        let current_time = check_queued_call(&ctx.accounts.pending_action, ACTION_REMOVE_CANDY_MACHINE, &candy_machine)?;
+        update_reward_pool(&mut ctx.accounts.pool, &mut ctx.accounts.vault, &ctx.accounts.cm_reward_per_token, current_time)?;
+        let vault = &mut ctx.accounts.vault;
+        let index = active_candy_machine(vault, &candy_machine);
+        if let Some(index) = index {
+            vault.retired[index] = true;
+            drop_retired_candy_machine(vault, index);
+        }
+
+        emit!(CandyMachineRemoved {
+            pool: ctx.accounts.pool.key(),
+            candy_machine,
+            timestamp: current_time,
+        });
+        Ok(())
+    }
+
+    /// Stakes an NFT of rarity `tier`, proven against the pool's rarity root by `proof`.
+    pub fn stake(ctx: Context<Stake>, tier: u8, proof: Vec<[u8; 32]>) -> Result<()> {
+        let pool = &mut ctx.accounts.pool;
+        if pool.pause_flags & PAUSE_STAKE != 0 {
+            return Err(ErrorCode::StakingPaused.into());
+        }
+        msg!("staking start");
+        msg!("Checking create");
+        let vault_index = find_candy_machine(&ctx.accounts.vault, &ctx.accounts.metadata_info.to_account_info())?;
+        msg!("Passed check candy machine");
+        verify_rarity_tier(pool, &ctx.accounts.nft_mint.key(), tier, &proof)?;
+        let user = &mut ctx.accounts.user;
+        let user_store = &mut ctx.accounts.user_store;
+        let mut reward_streams = load_reward_streams(&ctx.accounts.reward_streams)?;
+        update_rewards(
+            pool,
+            user,
+            user_store,
+            &mut ctx.accounts.vault,
+            &mut ctx.accounts.cm_reward_per_token,
+            &mut reward_streams,
+            &mut ctx.accounts.set_bonus,
+        )?;
+        save_reward_streams(&reward_streams)?;
+        msg!("updated rewards");
+        msg!("Start nft transfer");
+        // Transfer tokens into the stake vault.
+        {
+            let cpi_ctx = CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                token::Transfer {
+                    from: ctx.accounts.stake_from_account.to_account_info(),
+                    to: ctx.accounts.stake_to_account.to_account_info(),
+                    authority: ctx.accounts.owner.to_account_info(), //todo use user account as signer
+                },
+            );
+            token::transfer(cpi_ctx, 1 as u64)?;
+            msg!("End nft transfer");
+        }
+
+        let mint = ctx.accounts.stake_to_account.mint;
+        let unlock_time = add_staked_nft(pool, user, user_store, &mut ctx.accounts.vault, vault_index, mint, tier, false)?;
+
+        emit!(Staked {
+            pool: pool.key(),
+            owner: ctx.accounts.owner.key(),
+            store_id: user_store.store_id,
+            mint,
+            candy_machine: ctx.accounts.vault.candy_machines[vault_index],
+            tier,
+            unlock_time,
+            soft_staked: false,
+            timestamp: user.last_update_time,
+        });
+
+        fit_user_store(&ctx.accounts.user_store, &ctx.accounts.owner, &ctx.accounts.system_program)?;
+
+        Ok(())
+    }
+
+    pub fn unstake(ctx: Context<Stake>) -> Result<()> {
+        let pool = &mut ctx.accounts.pool;
+        let user = &mut ctx.accounts.user;
+        let vault = &mut ctx.accounts.vault;
+        let user_store = &mut ctx.accounts.user_store;
+
+        // The store records each NFT's candy machine, so NFTs of a removed candy machine can still leave.
+        let stake_to_account_mint = ctx.accounts.stake_to_account.mint;
+        let index = user_store.nft_mints.iter().position(|x| *x == stake_to_account_mint).ok_or(ErrorCode::NftNotStaked)?;
+        if user_store.soft_staked[index] {
+            return Err(ErrorCode::WrongStakeMode.into());
+        }
+        let current_time: u64 = current_timestamp()?;
+
+        let mut reward_streams = load_reward_streams(&ctx.accounts.reward_streams)?;
+        update_rewards(
+            pool,
+            user,
+            user_store,
+            vault,
+            &mut ctx.accounts.cm_reward_per_token,
+            &mut reward_streams,
+            &mut ctx.accounts.set_bonus,
+        )?;
+        save_reward_streams(&reward_streams)?;
+        check_unstake_lock(pool, vault, user_store, index, current_time)?;
+
+        // Transfer tokens from the pool vault to user vault.
+        {
+            let seeds = &[
+                pool.to_account_info().key.as_ref(),
+                &[pool.nonce],
+            ];
+            let pool_signer = &[&seeds[..]];
+
+            let cpi_ctx = CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                token::Transfer {
+                    from: ctx.accounts.stake_to_account.to_account_info(),
+                    to: ctx.accounts.stake_from_account.to_account_info(),
+                    authority: ctx.accounts.pool_signer.to_account_info(),
+                },
+                pool_signer,
+            );
+            token::transfer(cpi_ctx, 1 as u64)?;
+        }
+
+        let nft_candy_machine = remove_staked_nft(pool, user, user_store, vault, index)?;
+
+        emit!(Unstaked {
+            pool: pool.key(),
+            owner: ctx.accounts.owner.key(),
+            store_id: user_store.store_id,
+            mint: stake_to_account_mint,
+            candy_machine: nft_candy_machine,
+            soft_staked: false,
+            timestamp: current_time,
+        });
+
+        fit_user_store(&ctx.accounts.user_store, &ctx.accounts.owner, &ctx.accounts.system_program)?;
+
+        Ok(())
+    }
+
+    /// Stakes several NFTs at once. `remaining_accounts` holds one
+    /// (stake_from_account, stake_to_account, nft_mint, metadata_info) group per NFT,
+    /// and `tiers`/`proofs` hold each NFT's rarity tier and proof in the same order.
+    pub fn stake_many<'info>(
+        ctx: Context<'_, '_, '_, 'info, StakeMany<'info>>,
+        tiers: Vec<u8>,
+        proofs: Vec<Vec<[u8; 32]>>,
+    ) -> Result<()> {
+        let pool = &mut ctx.accounts.pool;
+        if pool.pause_flags & PAUSE_STAKE != 0 {
+            return Err(ErrorCode::StakingPaused.into());
+        }
+        let groups = stake_account_groups(ctx.remaining_accounts)?;
+        if tiers.len() != groups.len() || proofs.len() != groups.len() {
+            return Err(ErrorCode::InvalidRarityProof.into());
+        }
+        let user = &mut ctx.accounts.user;
+        let user_store = &mut ctx.accounts.user_store;
+        let mut reward_streams = load_reward_streams(&ctx.accounts.reward_streams)?;
+        update_rewards(
+            pool,
+            user,
+            user_store,
+            &mut ctx.accounts.vault,
+            &mut ctx.accounts.cm_reward_per_token,
+            &mut reward_streams,
+            &mut ctx.accounts.set_bonus,
+        )?;
+        save_reward_streams(&reward_streams)?;
+
+        for (i, group) in groups.enumerate() {
+            let (stake_from_account, stake_to_account, mint) = load_stake_accounts(group, &ctx.accounts.owner.key(), &ctx.accounts.pool_signer.key())?;
+            let vault_index = find_candy_machine(&ctx.accounts.vault, &group[3])?;
+            verify_rarity_tier(pool, &mint, tiers[i], &proofs[i])?;
+
+            let cpi_ctx = CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                token::Transfer {
+                    from: stake_from_account.to_account_info(),
+                    to: stake_to_account.to_account_info(),
+                    authority: ctx.accounts.owner.to_account_info(),
+                },
+            );
+            token::transfer(cpi_ctx, 1)?;
+
+            let unlock_time = add_staked_nft(pool, user, user_store, &mut ctx.accounts.vault, vault_index, mint, tiers[i], false)?;
+
+            emit!(Staked {
+                pool: pool.key(),
+                owner: ctx.accounts.owner.key(),
+                store_id: user_store.store_id,
+                mint,
+                candy_machine: ctx.accounts.vault.candy_machines[vault_index],
+                tier: tiers[i],
+                unlock_time,
+                soft_staked: false,
+                timestamp: user.last_update_time,
+            });
+        }
+
+        fit_user_store(&ctx.accounts.user_store, &ctx.accounts.owner, &ctx.accounts.system_program)?;
+
+        Ok(())
+    }
+
+    /// Unstakes several NFTs at once, taking the same account groups as `stake_many`.
+    pub fn unstake_many<'info>(ctx: Context<'_, '_, '_, 'info, StakeMany<'info>>) -> Result<()> {
+        let pool = &mut ctx.accounts.pool;
+        let user = &mut ctx.accounts.user;
+        let vault = &mut ctx.accounts.vault;
+        let user_store = &mut ctx.accounts.user_store;
+        let groups = stake_account_groups(ctx.remaining_accounts)?;
+        let current_time: u64 = current_timestamp()?;
+
+        let mut reward_streams = load_reward_streams(&ctx.accounts.reward_streams)?;
+        update_rewards(
+            pool,
+            user,
+            user_store,
+            vault,
+            &mut ctx.accounts.cm_reward_per_token,
+            &mut reward_streams,
+            &mut ctx.accounts.set_bonus,
+        )?;
+        save_reward_streams(&reward_streams)?;
+
+        let seeds = &[
+            pool.to_account_info().key.as_ref(),
+            &[pool.nonce],
+        ];
+        let pool_signer = &[&seeds[..]];
+
+        for group in groups {
+            let (stake_from_account, stake_to_account, mint) = load_stake_accounts(group, &ctx.accounts.owner.key(), &ctx.accounts.pool_signer.key())?;
+            let index = user_store.nft_mints.iter().position(|x| *x == mint).ok_or(ErrorCode::NftNotStaked)?;
+            if user_store.soft_staked[index] {
+                return Err(ErrorCode::WrongStakeMode.into());
+            }
+            check_unstake_lock(pool, vault, user_store, index, current_time)?;
+
+            let cpi_ctx = CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                token::Transfer {
+                    from: stake_to_account.to_account_info(),
+                    to: stake_from_account.to_account_info(),
+                    authority: ctx.accounts.pool_signer.to_account_info(),
+                },
+                pool_signer,
+            );
+            token::transfer(cpi_ctx, 1)?;
+
+            let nft_candy_machine = remove_staked_nft(pool, user, user_store, vault, index)?;
+
+            emit!(Unstaked {
+                pool: pool.key(),
+                owner: ctx.accounts.owner.key(),
+                store_id: user_store.store_id,
+                mint,
+                candy_machine: nft_candy_machine,
+                soft_staked: false,
+                timestamp: current_time,
+            });
+        }
+
+        fit_user_store(&ctx.accounts.user_store, &ctx.accounts.owner, &ctx.accounts.system_program)?;
+
+        Ok(())
+    }
+
+    /// Soft-stakes an NFT of rarity `tier`, proven against the pool's rarity root by `proof`.
+    pub fn soft_stake(ctx: Context<SoftStake>, tier: u8, proof: Vec<[u8; 32]>) -> Result<()> {
+        let pool = &mut ctx.accounts.pool;
+        if pool.pause_flags & PAUSE_STAKE != 0 {
+            return Err(ErrorCode::StakingPaused.into());
+        }
+        let vault_index = find_candy_machine(&ctx.accounts.vault, &ctx.accounts.metadata_info.to_account_info())?;
+        verify_rarity_tier(pool, &ctx.accounts.nft_mint.key(), tier, &proof)?;
+        let user = &mut ctx.accounts.user;
+        let user_store = &mut ctx.accounts.user_store;
+        let mut reward_streams = load_reward_streams(&ctx.accounts.reward_streams)?;
+        update_rewards(
+            pool,
+            user,
+            user_store,
+            &mut ctx.accounts.vault,
+            &mut ctx.accounts.cm_reward_per_token,
+            &mut reward_streams,
+            &mut ctx.accounts.set_bonus,
+        )?;
+        save_reward_streams(&reward_streams)?;
+
+        // Delegate the NFT to the pool signer and freeze it in the owner's wallet.
+        {
+            let cpi_ctx = CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                token::Approve {
+                    to: ctx.accounts.stake_from_account.to_account_info(),
+                    delegate: ctx.accounts.pool_signer.to_account_info(),
+                    authority: ctx.accounts.owner.to_account_info(),
+                },
+            );
+            token::approve(cpi_ctx, 1)?;
+
+            let seeds = &[
+                pool.to_account_info().key.as_ref(),
+                &[pool.nonce],
+            ];
+            freeze_delegated_nft(
+                FREEZE_DELEGATED_ACCOUNT,
+                &ctx.accounts.pool_signer,
+                &ctx.accounts.stake_from_account,
+                &ctx.accounts.edition,
+                &ctx.accounts.nft_mint,
+                &ctx.accounts.token_program,
+                &ctx.accounts.token_metadata_program,
+                &[&seeds[..]],
+            )?;
+        }
+
+        let mint = ctx.accounts.nft_mint.key();
+        let unlock_time = add_staked_nft(pool, user, user_store, &mut ctx.accounts.vault, vault_index, mint, tier, true)?;
+
+        emit!(Staked {
+            pool: pool.key(),
+            owner: ctx.accounts.owner.key(),
+            store_id: user_store.store_id,
+            mint,
+            candy_machine: ctx.accounts.vault.candy_machines[vault_index],
+            tier,
+            unlock_time,
+            soft_staked: true,
+            timestamp: user.last_update_time,
+        });
+
+        fit_user_store(&ctx.accounts.user_store, &ctx.accounts.owner, &ctx.accounts.system_program)?;
+
+        Ok(())
+    }
+
+    /// Returns a staked NFT without settling rewards, for when `unstake` fails on reward math.
+    /// The NFT's unsettled rewards are forfeited; lock periods still apply.
+    pub fn emergency_unstake(ctx: Context<EmergencyUnstake>) -> Result<()> {
+        let pool = &mut ctx.accounts.pool;
+        let user = &mut ctx.accounts.user;
+        let vault = &mut ctx.accounts.vault;
+        let user_store = &mut ctx.accounts.user_store;
+
+        let stake_to_account_mint = ctx.accounts.stake_to_account.mint;
+        let index = user_store.nft_mints.iter().position(|x| *x == stake_to_account_mint).ok_or(ErrorCode::NftNotStaked)?;
+        if user_store.soft_staked[index] {
+            return Err(ErrorCode::WrongStakeMode.into());
+        }
+        let current_time: u64 = current_timestamp()?;
+        // The lock only depends on time, so it holds even when reward math can't run.
+        if current_time < user_store.unlock_times[index] {
+            return Err(ErrorCode::StakeLocked.into());
+        }
+
+        // Transfer tokens from the pool vault to user vault.
+        {
+            let seeds = &[
+                pool.to_account_info().key.as_ref(),
+                &[pool.nonce],
+            ];
+            let pool_signer = &[&seeds[..]];
+
+            let cpi_ctx = CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                token::Transfer {
+                    from: ctx.accounts.stake_to_account.to_account_info(),
+                    to: ctx.accounts.stake_from_account.to_account_info(),
+                    authority: ctx.accounts.pool_signer.to_account_info(),
+                },
+                pool_signer,
+            );
+            token::transfer(cpi_ctx, 1)?;
+        }
+
+        let nft_candy_machine = remove_unsettled_nft(pool, user, user_store, vault, index);
+
+        emit!(EmergencyUnstaked {
+            pool: pool.key(),
+            owner: ctx.accounts.owner.key(),
+            store_id: user_store.store_id,
+            mint: stake_to_account_mint,
+            candy_machine: nft_candy_machine,
+            soft_staked: false,
+            timestamp: current_time,
+        });
+
+        fit_user_store(&ctx.accounts.user_store, &ctx.accounts.owner, &ctx.accounts.system_program)?;
+
+        Ok(())
+    }
+
+    /// Soft-stake counterpart of `emergency_unstake`.
+    pub fn emergency_soft_unstake(ctx: Context<EmergencySoftUnstake>) -> Result<()> {
+        let pool = &mut ctx.accounts.pool;
+        let user = &mut ctx.accounts.user;
+        let vault = &mut ctx.accounts.vault;
+        let user_store = &mut ctx.accounts.user_store;
+
+        let mint = ctx.accounts.nft_mint.key();
+        let index = user_store.nft_mints.iter().position(|x| *x == mint).ok_or(ErrorCode::NftNotStaked)?;
+        if !user_store.soft_staked[index] {
+            return Err(ErrorCode::WrongStakeMode.into());
+        }
+        let current_time: u64 = current_timestamp()?;
+        if current_time < user_store.unlock_times[index] {
+            return Err(ErrorCode::StakeLocked.into());
+        }
+
+        // Thaw the NFT and drop the pool signer's delegation.
+        {
+            let seeds = &[
+                pool.to_account_info().key.as_ref(),
+                &[pool.nonce],
+            ];
+            freeze_delegated_nft(
+                THAW_DELEGATED_ACCOUNT,
+                &ctx.accounts.pool_signer,
+                &ctx.accounts.stake_from_account,
+                &ctx.accounts.edition,
+                &ctx.accounts.nft_mint,
+                &ctx.accounts.token_program,
+                &ctx.accounts.token_metadata_program,
+                &[&seeds[..]],
+            )?;
+
+            let ix = spl_token::instruction::revoke(
+                &ctx.accounts.token_program.key(),
+                &ctx.accounts.stake_from_account.key(),
+                &ctx.accounts.owner.key(),
+                &[],
+            )?;
+            invoke(
+                &ix,
+                &[
+                    ctx.accounts.stake_from_account.to_account_info(),
+                    ctx.accounts.owner.to_account_info(),
+                    ctx.accounts.token_program.to_account_info(),
+                ],
+            )?;
+        }
+
+        let nft_candy_machine = remove_unsettled_nft(pool, user, user_store, vault, index);
+
+        emit!(EmergencyUnstaked {
+            pool: pool.key(),
+            owner: ctx.accounts.owner.key(),
+            store_id: user_store.store_id,
+            mint,
+            candy_machine: nft_candy_machine,
+            soft_staked: true,
+            timestamp: current_time,
+        });
+
+        fit_user_store(&ctx.accounts.user_store, &ctx.accounts.owner, &ctx.accounts.system_program)?;
+
+        Ok(())
+    }
+
+    pub fn soft_unstake(ctx: Context<SoftStake>) -> Result<()> {
+        let pool = &mut ctx.accounts.pool;
+        let user = &mut ctx.accounts.user;
+        let vault = &mut ctx.accounts.vault;
+        let user_store = &mut ctx.accounts.user_store;
+
+        let mint = ctx.accounts.nft_mint.key();
+        let index = user_store.nft_mints.iter().position(|x| *x == mint).ok_or(ErrorCode::NftNotStaked)?;
+        if !user_store.soft_staked[index] {
+            return Err(ErrorCode::WrongStakeMode.into());
+        }
+        let current_time: u64 = current_timestamp()?;
+
+        let mut reward_streams = load_reward_streams(&ctx.accounts.reward_streams)?;
+        update_rewards(
+            pool,
+            user,
+            user_store,
+            vault,
+            &mut ctx.accounts.cm_reward_per_token,
+            &mut reward_streams,
+            &mut ctx.accounts.set_bonus,
+        )?;
+        save_reward_streams(&reward_streams)?;
+        check_unstake_lock(pool, vault, user_store, index, current_time)?;
+
+        // Thaw the NFT and drop the pool signer's delegation.
+        {
+            let seeds = &[
+                pool.to_account_info().key.as_ref(),
+                &[pool.nonce],
+            ];
+            freeze_delegated_nft(
+                THAW_DELEGATED_ACCOUNT,
+                &ctx.accounts.pool_signer,
+                &ctx.accounts.stake_from_account,
+                &ctx.accounts.edition,
+                &ctx.accounts.nft_mint,
+                &ctx.accounts.token_program,
+                &ctx.accounts.token_metadata_program,
+                &[&seeds[..]],
+            )?;
+
+            let ix = spl_token::instruction::revoke(
+                &ctx.accounts.token_program.key(),
+                &ctx.accounts.stake_from_account.key(),
+                &ctx.accounts.owner.key(),
+                &[],
+            )?;
+            invoke(
+                &ix,
+                &[
+                    ctx.accounts.stake_from_account.to_account_info(),
+                    ctx.accounts.owner.to_account_info(),
+                    ctx.accounts.token_program.to_account_info(),
+                ],
+            )?;
+        }
+
+        let nft_candy_machine = remove_staked_nft(pool, user, user_store, vault, index)?;
+
+        emit!(Unstaked {
+            pool: pool.key(),
+            owner: ctx.accounts.owner.key(),
+            store_id: user_store.store_id,
+            mint,
+            candy_machine: nft_candy_machine,
+            soft_staked: true,
+            timestamp: current_time,
+        });
+
+        fit_user_store(&ctx.accounts.user_store, &ctx.accounts.owner, &ctx.accounts.system_program)?;
+
+        Ok(())
+    }
+
+    /// Claims the pool reward plus every reward stream. `remaining_accounts` holds one
+    /// (stream reward_vault, user reward_account) pair per stream, in stream order;
+    /// streams without a pair stay pending.
+    pub fn claim<'info>(ctx: Context<'_, '_, '_, 'info, ClaimReward<'info>>) -> Result<()> {
+        if ctx.accounts.pool.pause_flags & PAUSE_CLAIM != 0 {
+            return Err(ErrorCode::ClaimsPaused.into());
+        }
+        let user = &mut ctx.accounts.user;
+        let user_store = &mut ctx.accounts.user_store;
+        let mut reward_streams = load_reward_streams(&ctx.accounts.reward_streams)?;
+        update_rewards(
+            &mut ctx.accounts.pool,
+            user,
+            user_store,
+            &mut ctx.accounts.vault,
+            &mut ctx.accounts.cm_reward_per_token,
+            &mut reward_streams,
+            &mut ctx.accounts.set_bonus,
+        )?;
+        save_reward_streams(&reward_streams)?;
+
+        let seeds = &[
+            ctx.accounts.pool.to_account_info().key.as_ref(),
+            &[ctx.accounts.pool.nonce],
+        ];
+        let pool_signer = &[&seeds[..]];
+
+        // What locked NFTs would forfeit by unstaking early stays pending until they unlock.
+        let withheld = user_store.withheld;
+
+        if user_store.reward_token_pending > withheld {
+            let claimable = user_store.reward_token_pending - withheld;
+            let mut reward_amount = claimable;
+            let vault_balance = ctx.accounts.reward_vault.amount;
+
+            if vault_balance < reward_amount {
+                reward_amount = vault_balance;
+            }
+            // Whatever the vault can't cover stays pending for a later claim.
+            user_store.reward_token_pending = user_store.reward_token_pending.checked_sub(reward_amount).ok_or(ErrorCode::MathOverflow)?;
+            let pool = &mut ctx.accounts.pool;
+            pool.reward_liability = pool.reward_liability.saturating_sub(
+                                        (reward_amount as u128).checked_mul(REWARD_PRECISION).ok_or(ErrorCode::MathOverflow)?
+                                    );
+
+            if reward_amount > 0 {
+                let cpi_ctx = CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    token::Transfer {
+                        from: ctx.accounts.reward_vault.to_account_info(),
+                        to: ctx.accounts.reward_account.to_account_info(),
+                        authority: ctx.accounts.pool_signer.to_account_info(),
+                    },
+                    pool_signer,
+                );
+                token::transfer(cpi_ctx, reward_amount)?;
+            }
+
+            emit!(Claimed {
+                pool: ctx.accounts.pool.key(),
+                owner: ctx.accounts.owner.key(),
+                store_id: user_store.store_id,
+                reward_mint: ctx.accounts.pool.reward_mint,
+                amount: reward_amount,
+                timestamp: user.last_update_time,
+            });
+
+            if reward_amount < claimable {
+                emit!(ClaimShortfall {
+                    pool: ctx.accounts.pool.key(),
+                    owner: ctx.accounts.owner.key(),
+                    store_id: user_store.store_id,
+                    reward_mint: ctx.accounts.pool.reward_mint,
+                    paid: reward_amount,
+                    unpaid: claimable - reward_amount,
+                    timestamp: user.last_update_time,
+                });
+            }
+        }
+
+        let streams = reward_streams.as_ref().map(|r| r.streams.as_slice()).unwrap_or(&[]);
+        let pairs = ctx.remaining_accounts.chunks_exact(2);
+        if !pairs.remainder().is_empty() || pairs.len() > streams.len() {
+            return Err(ErrorCode::InvalidRemainingAccounts.into());
+        }
+        for (i, pair) in pairs.enumerate() {
+            let stream = &streams[i];
+            let stream_vault: Account<TokenAccount> = Account::try_from(&pair[0])?;
+            let reward_account: Account<TokenAccount> = Account::try_from(&pair[1])?;
+            if stream_vault.key() != stream.reward_vault || !pair[0].is_writable || !pair[1].is_writable {
+                return Err(ErrorCode::InvalidRemainingAccounts.into());
+            }
+            if reward_account.mint != stream.reward_mint {
+                return Err(ErrorCode::InvalidRemainingAccounts.into());
+            }
+
+            let pending: u64 = user_store.stream_rewards_pending[i].checked_div(REWARD_PRECISION).ok_or(ErrorCode::MathOverflow)?
+                                                                   .try_into().map_err(|_| ErrorCode::MathOverflow)?;
+            if pending == 0 {
+                continue;
+            }
+            // As with the pool reward, whatever the stream vault can't cover stays pending.
+            let reward_amount = pending.min(stream_vault.amount);
+            user_store.stream_rewards_pending[i] = user_store.stream_rewards_pending[i].checked_sub(
+                                                        (reward_amount as u128).checked_mul(REWARD_PRECISION).ok_or(ErrorCode::MathOverflow)?
+                                                    ).ok_or(ErrorCode::MathOverflow)?;
+
+            if reward_amount > 0 {
+                let cpi_ctx = CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    token::Transfer {
+                        from: pair[0].clone(),
+                        to: pair[1].clone(),
+                        authority: ctx.accounts.pool_signer.to_account_info(),
+                    },
+                    pool_signer,
+                );
+                token::transfer(cpi_ctx, reward_amount)?;
+            }
+
+            emit!(Claimed {
+                pool: ctx.accounts.pool.key(),
+                owner: ctx.accounts.owner.key(),
+                store_id: user_store.store_id,
+                reward_mint: stream.reward_mint,
+                amount: reward_amount,
+                timestamp: user.last_update_time,
+            });
+
+            if reward_amount < pending {
+                emit!(ClaimShortfall {
+                    pool: ctx.accounts.pool.key(),
+                    owner: ctx.accounts.owner.key(),
+                    store_id: user_store.store_id,
+                    reward_mint: stream.reward_mint,
+                    paid: reward_amount,
+                    unpaid: pending - reward_amount,
+                    timestamp: user.last_update_time,
+                });
+            }
+        }
+
+        fit_user_store(&ctx.accounts.user_store, &ctx.accounts.owner, &ctx.accounts.system_program)?;
+
+        Ok(())
+    }
+
+    pub fn close_user(ctx: Context<CloseUser>) -> Result<()> {
+        let pool = &mut ctx.accounts.pool;
+        pool.user_stake_count = pool.user_stake_count.checked_sub(1).ok_or(ErrorCode::MathOverflow)?;
+
+        emit!(UserClosed {
+            pool: pool.key(),
+            owner: ctx.accounts.owner.key(),
+            timestamp: current_timestamp()?,
+        });
+        Ok(())
+    }
+
+    pub fn close_user_store(ctx: Context<CloseUserStore>) -> Result<()> {
+        let user = &mut ctx.accounts.user;
+        user.stores = user.stores.checked_sub(1).ok_or(ErrorCode::MathOverflow)?;
+
+        emit!(UserStoreClosed {
+            pool: ctx.accounts.pool.key(),
+            owner: ctx.accounts.owner.key(),
+            store_id: ctx.accounts.user_store.store_id,
+            timestamp: current_timestamp()?,
+        });
+        Ok(())
+    }
+
+    pub fn close_n_account(ctx: Context<CloseNAccount>) -> Result<()> {
+        let pool = &ctx.accounts.pool;
+        let seeds = &[
+            pool.to_account_info().key.as_ref(),
+            &[pool.nonce],
+        ];
+        let pool_signer = &[&seeds[..]];
+        anchor_spl::token::close_account(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                anchor_spl::token::CloseAccount{
+                    account: ctx.accounts.close_account.to_account_info(),
+                    destination: ctx.accounts.owner.to_account_info(),
+                    authority: ctx.accounts.pool_signer.to_account_info(),
+                },
+                pool_signer
+        ))?;
+
+        emit!(TokenAccountClosed {
+            pool: pool.key(),
+            account: ctx.accounts.close_account.key(),
+            timestamp: current_timestamp()?,
+        });
+
+        Ok(())
+    }
+
+    pub fn execute_withdraw_reward(ctx: Context<ExecuteWithdrawReward>) -> Result<()> {
+        let current_time = check_pending_action(&ctx.accounts.pending_action, ACTION_WITHDRAW_REWARD)?;
+        let amount = ctx.accounts.pending_action.value;
+
+        let seeds = &[
+            ctx.accounts.pool.to_account_info().key.as_ref(),
+            &[ctx.accounts.pool.nonce],
+        ];
+        let pool_signer = &[&seeds[..]];
+
+        let mut withdraw_amount = amount;
+        let vault_balance = ctx.accounts.reward_vault.amount;
+
+        if vault_balance < withdraw_amount {
+            withdraw_amount = vault_balance;
+        }
+
+        if withdraw_amount > 0 {
+            let cpi_ctx = CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                token::Transfer {
+                    from: ctx.accounts.reward_vault.to_account_info(),
+                    to: ctx.accounts.reward_account.to_account_info(),
+                    authority: ctx.accounts.pool_signer.to_account_info(),
+                },
+                pool_signer,
+            );
+            token::transfer(cpi_ctx, withdraw_amount)?;
+        }
+
+        emit!(RewardWithdrawn {
+            pool: ctx.accounts.pool.key(),
+            reward_account: ctx.accounts.reward_account.key(),
+            amount: withdraw_amount,
+            timestamp: current_time,
+        });
+
+        Ok(())
+    }
+
+    pub fn deposit_reward(ctx: Context<DepositReward>, amount: u64) -> Result<()> {
+        let depositor_balance = ctx.accounts.reward_depositor.amount;
+        let mut deposit_amount = amount;
+        if amount > depositor_balance {
+            deposit_amount = depositor_balance;
+        }
+        //lp lockup
+        let cpi_ctx = CpiContext::new(
+            ctx.accounts.token_program.to_account_info(),
+            token::Transfer {
+                from: ctx.accounts.reward_depositor.to_account_info(),
+                to: ctx.accounts.reward_vault.to_account_info(),
+                authority: ctx.accounts.reward_deposit_authority.to_account_info(),
+            },
+        );
+        token::transfer(cpi_ctx, deposit_amount)?;
+
+        emit!(RewardDeposited {
+            pool: ctx.accounts.pool.key(),
+            depositor: ctx.accounts.reward_depositor.key(),
+            amount: deposit_amount,
+            timestamp: current_timestamp()?,
+        });
+        
+        Ok(())
+    }
+
+    pub fn report_solvency(ctx: Context<ReportSolvency>) -> Result<()> {
+        let pool = &ctx.accounts.pool;
+        let vault = &ctx.accounts.vault;
+        let current_time: u64 = current_timestamp()?;
+        if current_time < vault.last_update_time {
+            return Err(ErrorCode::InvalidTimestamp.into());
+        }
+
+        // Project the liability to now without writing the accumulators.
+        let mut reward_liability = pool.reward_liability;
+        let mut emission_per_day: u128 = 0;
+        let emitted = emitted_time(pool, vault.last_update_time, current_time)?;
+        for i in 0..vault.candy_machines.len() {
+            if vault.retired[i] {
+                continue;
+            }
+            let reward_per_token = candy_machine_reward_per_token(pool, &ctx.accounts.cm_reward_per_token, vault.candy_machines[i]);
+            let staked_weight = vault.staked_weights[i] as u128;
+            reward_liability = reward_liability.checked_add(
+                                    staked_weight.checked_mul(
+                                        scheduled_reward_per_share(reward_per_token, emitted)?
+                                    ).ok_or(ErrorCode::MathOverflow)?
+                                    .checked_div(BPS as u128).ok_or(ErrorCode::MathOverflow)?
+                                ).ok_or(ErrorCode::MathOverflow)?;
+            emission_per_day = emission_per_day.checked_add(
+                                    staked_weight.checked_mul(reward_per_token as u128).ok_or(ErrorCode::MathOverflow)?
+                                ).ok_or(ErrorCode::MathOverflow)?;
+        }
+        // Staked weights are in basis points; emission is at the schedule's current step.
+        let emission_per_day = emission_per_day.checked_mul(emission_multiplier(pool, current_time) as u128).ok_or(ErrorCode::MathOverflow)?
+                                               .checked_div((BPS as u128) * (BPS as u128)).ok_or(ErrorCode::MathOverflow)?;
+
+        let reward_liability: u64 = reward_liability.checked_div(REWARD_PRECISION).ok_or(ErrorCode::MathOverflow)?
+                                                    .try_into().map_err(|_| ErrorCode::MathOverflow)?;
+        let reward_vault_balance = ctx.accounts.reward_vault.amount;
+        let runway = if emission_per_day == 0 {
+            u64::MAX
+        } else {
+            (reward_vault_balance.saturating_sub(reward_liability) as u128)
+                .checked_mul(SECONDS_PER_DAY as u128).ok_or(ErrorCode::MathOverflow)?
+                .checked_div(emission_per_day).ok_or(ErrorCode::MathOverflow)?
+                .try_into().unwrap_or(u64::MAX)
+        };
+
+        emit!(SolvencyReport {
+            pool: pool.key(),
+            reward_vault_balance,
+            reward_liability,
+            emission_per_day: emission_per_day.try_into().unwrap_or(u64::MAX),
+            runway,
+            timestamp: current_time,
+        });
+
+        Ok(())
+    }
+
+}
+
+#[derive(Accounts)]
+#[instruction(pool_nonce: u8, vault_nonce: u8)]
+pub struct InitializePool<'info> {
+    /// CHECK: This is pool authority account
+    authority: UncheckedAccount<'info>,
+    reward_mint: Box<Account<'info, Mint>>,
+    #[account(
+        constraint = reward_vault.mint == reward_mint.key(),
+        constraint = reward_vault.owner == pool_signer.key(),
+        constraint = reward_vault.close_authority == COption::None,
+    )]
+    reward_vault: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        seeds = [
+            pool.to_account_info().key.as_ref()
+        ],
+        bump = pool_nonce,
+    )]
+    /// CHECK: This is pool signer with seeds
+    pool_signer: UncheckedAccount<'info>,
+
+    #[account(
+        zero,
+    )]
+    pool: Box<Account<'info, Pool>>,
+    #[account(
+        init,
+        payer = owner,
+        seeds = [
+            owner.key.as_ref(), 
+            pool.to_account_info().key.as_ref()
+        ],
+        bump,
+        space = 10240,
+    )]
+    vault: Box<Account<'info, Vault>>,
+    #[account(mut)]
+    owner: Signer<'info>,
+    
+    token_program: Program<'info, Token>,
+    system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ProposeAuthority<'info> {
+    #[account(
+        mut,
+        has_one = authority,
+    )]
+    pool: Box<Account<'info, Pool>>,
+    authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct AcceptAuthority<'info> {
+    #[account(
+        mut,
+        constraint = pool.pending_authority == pending_authority.key() @ ErrorCode::NotPendingAuthority,
+    )]
+    pool: Box<Account<'info, Pool>>,
+    pending_authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetRoles<'info> {
+    #[account(
+        mut,
+        has_one = authority,
+    )]
+    pool: Box<Account<'info, Pool>>,
+    authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(nonce: u8)]
+pub struct QueueAction<'info> {
+    #[account(
+        mut,
+        constraint = pool.pause_flags & PAUSE_ADMIN == 0 @ ErrorCode::AdminPaused,
+    )]
+    pool: Box<Account<'info, Pool>>,
+    #[account(
+        init,
+        payer = authority,
+        seeds = [
+            pool.to_account_info().key.as_ref(),
+            "pending_action".as_bytes(),
+            &pool.next_action_id.to_le_bytes(),
+        ],
+        bump,
+        space = 8 + 32 + 8 + 32 + 1 + 8 + 32 + (4 + MAX_ACTION_ARGS) + 8 + 1,
+    )]
+    pending_action: Box<Account<'info, PendingAction>>,
+    #[account(mut)]
+    authority: Signer<'info>,
+    // Misc.
+    system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetTimelockDelay<'info> {
+    #[account(
+        mut,
+        has_one = authority,
+    )]
+    pool: Box<Account<'info, Pool>>,
+    authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ExecuteSetTimelockDelay<'info> {
+    #[account(
+        mut,
+        constraint = pool.pause_flags & PAUSE_ADMIN == 0 @ ErrorCode::AdminPaused,
+    )]
+    pool: Box<Account<'info, Pool>>,
+    #[account(
+        mut,
+        close = proposer,
+        has_one = pool,
+        has_one = proposer,
+    )]
+    pending_action: Box<Account<'info, PendingAction>>,
+    #[account(mut)]
+    /// CHECK: Gets the pending action's rent back; checked by has_one.
+    proposer: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CancelPendingAction<'info> {
+    #[account(
+        constraint = pool.authority == authority.key() || pending_action.proposer == authority.key() @ ErrorCode::Unauthorized,
+    )]
+    pool: Box<Account<'info, Pool>>,
+    #[account(
+        mut,
+        close = proposer,
+        has_one = pool,
+        has_one = proposer,
+    )]
+    pending_action: Box<Account<'info, PendingAction>>,
+    #[account(mut)]
+    /// CHECK: Gets the pending action's rent back; checked by has_one.
+    proposer: UncheckedAccount<'info>,
+    authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ExecuteSetRewardPerToken<'info> {
+    // Stake instance.
+    #[account(
+        mut,
+        has_one = vault,
+        constraint = pool.pause_flags & PAUSE_ADMIN == 0 @ ErrorCode::AdminPaused,
+    )]
+    pool: Box<Account<'info, Pool>>,
+    #[account(
+        mut,
+    )]
+    vault: Box<Account<'info, Vault>>,
+    #[account(
+        seeds = [
+            pool.to_account_info().key.as_ref(),
+            "reward_per_token".as_bytes(),
+        ],
+        bump = cm_reward_per_token.nonce,
+    )]
+    cm_reward_per_token: Box<Account<'info, CandyMachineRewardPerToken>>,
+    #[account(
+        mut,
+        close = proposer,
+        has_one = pool,
+        has_one = proposer,
+    )]
+    pending_action: Box<Account<'info, PendingAction>>,
+    #[account(mut)]
+    /// CHECK: Gets the pending action's rent back; checked by has_one.
+    proposer: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetEmissionSchedule<'info> {
+    // Stake instance.
+    #[account(
+        mut,
+        constraint = pool.rate_manager == authority.key() @ ErrorCode::Unauthorized,
+        has_one = vault,
+        constraint = pool.pause_flags & PAUSE_ADMIN == 0 @ ErrorCode::AdminPaused,
+    )]
+    pool: Box<Account<'info, Pool>>,
+    #[account(
+        mut,
+    )]
+    vault: Box<Account<'info, Vault>>,
+    #[account(
+        seeds = [
+            pool.to_account_info().key.as_ref(),
+            "reward_per_token".as_bytes(),
+        ],
+        bump = cm_reward_per_token.nonce,
+    )]
+    cm_reward_per_token: Box<Account<'info, CandyMachineRewardPerToken>>,
+    #[account(
+        mut,
+        seeds = [
+            pool.to_account_info().key.as_ref(),
+            "set_bonus".as_bytes(),
+        ],
+        bump = set_bonus.nonce,
+    )]
+    set_bonus: Box<Account<'info, SetBonus>>,
+    #[account(
+        mut,
+        close = proposer,
+        has_one = pool,
+        has_one = proposer,
+    )]
+    pending_action: Box<Account<'info, PendingAction>>,
+    #[account(mut)]
+    /// CHECK: Gets the pending action's rent back; checked by has_one.
+    proposer: UncheckedAccount<'info>,
+    authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetRarityRoot<'info> {
+    // Stake instance.
+    #[account(
+        mut,
+        constraint = pool.rate_manager == authority.key() @ ErrorCode::Unauthorized,
+        constraint = pool.pause_flags & PAUSE_ADMIN == 0 @ ErrorCode::AdminPaused,
+    )]
+    pool: Box<Account<'info, Pool>>,
+    #[account(
+        mut,
+        close = proposer,
+        has_one = pool,
+        has_one = proposer,
+    )]
+    pending_action: Box<Account<'info, PendingAction>>,
+    #[account(mut)]
+    /// CHECK: Gets the pending action's rent back; checked by has_one.
+    proposer: UncheckedAccount<'info>,
+    authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(nonce: u8)]
+pub struct CreateCandyMachineRewardPerToken<'info> {
+    // Stake instance.
+    #[account(
+        constraint = pool.rate_manager == authority.key() @ ErrorCode::Unauthorized,
+        constraint = pool.pause_flags & PAUSE_ADMIN == 0 @ ErrorCode::AdminPaused,
+    )]
+    pool: Box<Account<'info, Pool>>,
+    #[account(
+        init,
+        payer = authority,
+        seeds = [
+            pool.to_account_info().key.as_ref(),
+            "reward_per_token".as_bytes(),
+        ],
+        bump,
+        space = 10240,
+    )]
+    cm_reward_per_token: Box<Account<'info, CandyMachineRewardPerToken>>,
+    #[account(mut)]
+    authority: Signer<'info>,
+    // Misc.
+    system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetCandyMachineRewardPerToken<'info> {
+    #[account(
+        mut,
+        constraint = pool.rate_manager == authority.key() @ ErrorCode::Unauthorized,
+        has_one = vault,
+        constraint = pool.pause_flags & PAUSE_ADMIN == 0 @ ErrorCode::AdminPaused,
+    )]
+    pool: Box<Account<'info, Pool>>,
+    #[account(
+        mut,
+    )]
+    vault: Box<Account<'info, Vault>>,
+    #[account(
+        mut,
+        seeds = [
+            pool.to_account_info().key.as_ref(),
+            "reward_per_token".as_bytes(),
+        ],
+        bump = cm_reward_per_token.nonce,
+    )]
+    cm_reward_per_token: Box<Account<'info, CandyMachineRewardPerToken>>,
+    #[account(
+        mut,
+        close = proposer,
+        has_one = pool,
+        has_one = proposer,
+    )]
+    pending_action: Box<Account<'info, PendingAction>>,
+    #[account(mut)]
+    /// CHECK: Gets the pending action's rent back; checked by has_one.
+    proposer: UncheckedAccount<'info>,
+    authority: Signer<'info>,
+    // Misc.
+    system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(nonce: u8)]
+pub struct CreateRewardStreams<'info> {
+    // Stake instance.
+    #[account(
+        constraint = pool.rate_manager == authority.key() @ ErrorCode::Unauthorized,
+        constraint = pool.pause_flags & PAUSE_ADMIN == 0 @ ErrorCode::AdminPaused,
+    )]
+    pool: Box<Account<'info, Pool>>,
+    #[account(
+        init,
+        payer = authority,
+        seeds = [
+            pool.to_account_info().key.as_ref(),
+            "reward_streams".as_bytes(),
+        ],
+        bump,
+        space = 10240,
+    )]
+    reward_streams: Box<Account<'info, RewardStreams>>,
+    #[account(mut)]
+    authority: Signer<'info>,
+    // Misc.
+    system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct AddRewardStream<'info> {
+    #[account(
+        constraint = pool.rate_manager == authority.key() @ ErrorCode::Unauthorized,
+        constraint = pool.pause_flags & PAUSE_ADMIN == 0 @ ErrorCode::AdminPaused,
+    )]
+    pool: Box<Account<'info, Pool>>,
+    #[account(
+        mut,
+        seeds = [
+            pool.to_account_info().key.as_ref(),
+            "reward_streams".as_bytes(),
+        ],
+        bump = reward_streams.nonce,
+    )]
+    reward_streams: Box<Account<'info, RewardStreams>>,
+    reward_mint: Box<Account<'info, Mint>>,
+    #[account(
+        constraint = reward_vault.mint == reward_mint.key(),
+        constraint = reward_vault.owner == pool_signer.key(),
+        constraint = reward_vault.close_authority == COption::None,
+    )]
+    reward_vault: Box<Account<'info, TokenAccount>>,
+    #[account(
+        seeds = [
+            pool.to_account_info().key.as_ref()
+        ],
+        bump = pool.nonce,
+    )]
+    /// CHECK: This is pool signer with seeds
+    pool_signer: UncheckedAccount<'info>,
+    authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetRewardStream<'info> {
+    #[account(
+        constraint = pool.rate_manager == authority.key() @ ErrorCode::Unauthorized,
+        constraint = pool.pause_flags & PAUSE_ADMIN == 0 @ ErrorCode::AdminPaused,
+    )]
+    pool: Box<Account<'info, Pool>>,
+    #[account(
+        mut,
+        seeds = [
+            pool.to_account_info().key.as_ref(),
+            "reward_streams".as_bytes(),
+        ],
+        bump = reward_streams.nonce,
+    )]
+    reward_streams: Box<Account<'info, RewardStreams>>,
+    #[account(
+        mut,
+        close = proposer,
+        has_one = pool,
+        has_one = proposer,
+    )]
+    pending_action: Box<Account<'info, PendingAction>>,
+    #[account(mut)]
+    /// CHECK: Gets the pending action's rent back; checked by has_one.
+    proposer: UncheckedAccount<'info>,
+    authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(nonce: u8)]
+pub struct CreateSetBonus<'info> {
+    // Stake instance.
+    #[account(
+        constraint = pool.rate_manager == authority.key() @ ErrorCode::Unauthorized,
+        constraint = pool.pause_flags & PAUSE_ADMIN == 0 @ ErrorCode::AdminPaused,
+    )]
+    pool: Box<Account<'info, Pool>>,
+    #[account(
+        init,
+        payer = authority,
+        seeds = [
+            pool.to_account_info().key.as_ref(),
+            "set_bonus".as_bytes(),
+        ],
+        bump,
+        space = 8 + (4 + 32 * MAX_SET_BONUS_CANDY_MACHINES) + 2 + 1
+                  + 16 + 8 + (4 + 32 * MAX_SET_BONUS_CANDY_MACHINES) + 16 + 8 + 16,
+    )]
+    set_bonus: Box<Account<'info, SetBonus>>,
+    #[account(mut)]
+    authority: Signer<'info>,
+    // Misc.
+    system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetSetBonus<'info> {
+    #[account(
+        constraint = pool.rate_manager == authority.key() @ ErrorCode::Unauthorized,
+        constraint = pool.pause_flags & PAUSE_ADMIN == 0 @ ErrorCode::AdminPaused,
+    )]
+    pool: Box<Account<'info, Pool>>,
+    #[account(
+        mut,
+        seeds = [
+            pool.to_account_info().key.as_ref(),
+            "set_bonus".as_bytes(),
+        ],
+        bump = set_bonus.nonce,
+    )]
+    set_bonus: Box<Account<'info, SetBonus>>,
+    #[account(
+        mut,
+        close = proposer,
+        has_one = pool,
+        has_one = proposer,
+    )]
+    pending_action: Box<Account<'info, PendingAction>>,
+    #[account(mut)]
+    /// CHECK: Gets the pending action's rent back; checked by has_one.
+    proposer: UncheckedAccount<'info>,
+    authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(nonce: u8, store_nonce: u8)]
+pub struct CreateUser<'info> {
+    // Stake instance.
+    #[account(
+        mut,
+        constraint = pool.pause_flags & PAUSE_STAKE == 0 @ ErrorCode::StakingPaused,
+    )]
+    pool: Box<Account<'info, Pool>>,
+    // Member.
+    #[account(
+        init,
+        payer = owner,
+        seeds = [
+            owner.key.as_ref(), 
+            pool.to_account_info().key.as_ref(),
+            "user".as_bytes()
+        ],
+        bump,
+        space = USER_SPACE,
+    )]
+    user: Box<Account<'info, User>>,
+    #[account(
+        init,
+        payer = owner,
+        seeds = [
+            owner.key.as_ref(), 
+            pool.to_account_info().key.as_ref(),
+            "user".as_bytes(),
+            &[1]
+        ],
+        bump,
+    )]
+    user_store: Box<Account<'info, UserStore>>,
+    #[account(mut)]
+    owner: Signer<'info>,
+    // Misc.
+    system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(nonce: u8)]
+pub struct CreateUserStore<'info> {
+    // Stake instance.
+    #[account(
+        mut,
+        constraint = pool.pause_flags & PAUSE_STAKE == 0 @ ErrorCode::StakingPaused,
+    )]
+    pool: Box<Account<'info, Pool>>,
+    // Member.
+    #[account(
+        mut,
+        has_one = owner,
+        has_one = pool,
+        seeds = [
+            owner.key.as_ref(), 
+            pool.to_account_info().key.as_ref(),
+            "user".as_bytes(),
+        ],
+        bump = user.nonce,
+    )]
+    user: Box<Account<'info, User>>,
+    #[account(
+        init,
+        payer = owner,
+        seeds = [
+            owner.key.as_ref(), 
+            pool.to_account_info().key.as_ref(),
+            "user".as_bytes(),
+            &[user.last_store_id + 1]
+        ],
+        bump,
+    )]
+    user_store: Box<Account<'info, UserStore>>,
+    #[account(mut)]
+    owner: Signer<'info>,
+    // Misc.
+    system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-#[instruction(pool_nonce: u8, vault_nonce: u8)]
-pub struct InitializePool<'info> {
-    /// CHECK: This is pool authority account
-    authority: UncheckedAccount<'info>,
-    reward_mint: Box<Account<'info, Mint>>,
+pub struct Pause<'info> {
     #[account(
-        constraint = reward_vault.mint == reward_mint.key(),
-        constraint = reward_vault.owner == pool_signer.key(),
-        constraint = reward_vault.close_authority == COption::None,
+        mut, 
+        constraint = pool.pauser == authority.key() @ ErrorCode::Unauthorized,
     )]
-    reward_vault: Box<Account<'info, TokenAccount>>,
+    pool: Box<Account<'info, Pool>>,
+    authority: Signer<'info>,
 
     #[account(
         seeds = [
             pool.to_account_info().key.as_ref()
         ],
-        bump = pool_nonce,
+        bump = pool.nonce,
     )]
     /// CHECK: This is pool signer with seeds
     pool_signer: UncheckedAccount<'info>,
+    token_program: Program<'info, Token>,
+}
 
+#[derive(Accounts)]
+pub struct Unpause<'info> {
     #[account(
-        zero,
+        mut, 
+        has_one = authority,
     )]
     pool: Box<Account<'info, Pool>>,
+    authority: Signer<'info>,
+
     #[account(
-        init,
-        payer = owner,
         seeds = [
-            owner.key.as_ref(), 
             pool.to_account_info().key.as_ref()
         ],
-        bump,
-        space = 10240,
+        bump = pool.nonce,
     )]
-    vault: Box<Account<'info, Vault>>,
-    #[account(mut)]
-    owner: Signer<'info>,
-    
+    /// CHECK: This is pool signer with seeds
+    pool_signer: UncheckedAccount<'info>,
     token_program: Program<'info, Token>,
-    system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-pub struct SetRewardPerToken<'info> {
-    // Stake instance.
+pub struct DepositReward<'info> {
     #[account(
         mut,
-        has_one = authority,
-        constraint = !pool.paused,
+        constraint = reward_vault.owner == pool_signer.key(),
+    )]
+    reward_vault: Box<Account<'info, TokenAccount>>,
+    #[account(
+        mut,
+    )]
+    reward_depositor: Box<Account<'info, TokenAccount>>,
+    reward_deposit_authority: Signer<'info>,
+
+    #[account(
+        mut, 
+        constraint = pool.treasurer == authority.key() @ ErrorCode::Unauthorized,
+        constraint = pool.pause_flags & PAUSE_ADMIN == 0 @ ErrorCode::AdminPaused,
     )]
     pool: Box<Account<'info, Pool>>,
     authority: Signer<'info>,
-    // Misc.
-    system_program: Program<'info, System>,
+
+    #[account(
+        seeds = [
+            pool.to_account_info().key.as_ref()
+        ],
+        bump = pool.nonce,
+    )]
+    /// CHECK: This is pool signer with seeds
+    pool_signer: UncheckedAccount<'info>,
+    token_program: Program<'info, Token>,
 }
 
 #[derive(Accounts)]
-#[instruction(nonce: u8)]
-pub struct CreateCandyMachineRewardPerToken<'info> {
-    // Stake instance.
+pub struct Stake<'info> {
+    // Global accounts for the staking instance.
     #[account(
-        has_one = authority,
-        constraint = !pool.paused,
+        mut,
+        has_one = vault,
     )]
     pool: Box<Account<'info, Pool>>,
     #[account(
-        init,
-        payer = authority,
+        mut,
+    )]
+    vault: Box<Account<'info, Vault>>,
+    #[account(
+        mut,
+        constraint = stake_to_account.owner == *pool_signer.key,
+        constraint = stake_to_account.mint == nft_mint.key() @ ErrorCode::InvalidNftMint,
+    )]
+    stake_to_account: Box<Account<'info, TokenAccount>>,
+
+    // User.
+    #[account(
+        mut, 
+        has_one = owner, 
+        has_one = pool,
+        seeds = [
+            owner.key.as_ref(), 
+            pool.to_account_info().key.as_ref(),
+            "user".as_bytes()
+        ],
+        bump = user.nonce,
+    )]
+    user: Box<Account<'info, User>>,
+    // User Store.
+    #[account(
+        mut, 
+        has_one = owner, 
+        seeds = [
+            owner.key.as_ref(), 
+            pool.to_account_info().key.as_ref(),
+            "user".as_bytes(),
+            &[user_store.store_id]
+        ],
+        bump = user_store.nonce,
+    )]
+    user_store: Box<Account<'info, UserStore>>,
+    #[account(
+        mut,
         seeds = [
             pool.to_account_info().key.as_ref(),
             "reward_per_token".as_bytes(),
         ],
-        bump,
-        space = 10240,
+        bump = cm_reward_per_token.nonce,
     )]
     cm_reward_per_token: Box<Account<'info, CandyMachineRewardPerToken>>,
+    #[account(
+        mut,
+        seeds = [
+            pool.to_account_info().key.as_ref(),
+            "reward_streams".as_bytes(),
+        ],
+        bump,
+    )]
+    /// CHECK: The pool's reward streams PDA. Pools that never ran `create_reward_streams` have none.
+    reward_streams: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        seeds = [
+            pool.to_account_info().key.as_ref(),
+            "set_bonus".as_bytes(),
+        ],
+        bump = set_bonus.nonce,
+    )]
+    set_bonus: Box<Account<'info, SetBonus>>,
     #[account(mut)]
-    authority: Signer<'info>,
+    owner: Signer<'info>,
+    #[account(
+        mut,
+        constraint = stake_from_account.mint == nft_mint.key() @ ErrorCode::InvalidNftMint,
+    )]
+    stake_from_account: Box<Account<'info, TokenAccount>>,
+    #[account(
+        constraint = nft_mint.supply == 1 @ ErrorCode::InvalidNftMint,
+        constraint = nft_mint.decimals == 0 @ ErrorCode::InvalidNftMint,
+    )]
+    nft_mint: Box<Account<'info, Mint>>,
+    #[account(
+        owner = metaplex_token_metadata::id(),
+        seeds = [
+            metaplex_token_metadata::state::PREFIX.as_bytes(),
+            metaplex_token_metadata::id().as_ref(),
+            nft_mint.key().as_ref(),
+        ],
+        seeds::program = metaplex_token_metadata::id(),
+        bump,
+    )]
+    /// CHECK: This is the metadata PDA of nft_mint, owned by the token metadata program.
+    metadata_info: UncheckedAccount<'info>,
+
+    // Program signers.
+    #[account(
+        seeds = [
+            pool.to_account_info().key.as_ref()
+        ],
+        bump = pool.nonce,
+    )]
+    /// CHECK: This is pool signer with seeds
+    pool_signer: UncheckedAccount<'info>,
+
     // Misc.
+    token_program: Program<'info, Token>,
     system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-pub struct SetCandyMachineRewardPerToken<'info> {
+pub struct StakeMany<'info> {
+    // Global accounts for the staking instance.
     #[account(
-        has_one = authority,
-        constraint = !pool.paused,
+        mut,
+        has_one = vault,
     )]
     pool: Box<Account<'info, Pool>>,
+    #[account(
+        mut,
+    )]
+    vault: Box<Account<'info, Vault>>,
+
+    // User.
+    #[account(
+        mut, 
+        has_one = owner, 
+        has_one = pool,
+        seeds = [
+            owner.key.as_ref(), 
+            pool.to_account_info().key.as_ref(),
+            "user".as_bytes()
+        ],
+        bump = user.nonce,
+    )]
+    user: Box<Account<'info, User>>,
+    // User Store.
+    #[account(
+        mut, 
+        has_one = owner, 
+        seeds = [
+            owner.key.as_ref(), 
+            pool.to_account_info().key.as_ref(),
+            "user".as_bytes(),
+            &[user_store.store_id]
+        ],
+        bump = user_store.nonce,
+    )]
+    user_store: Box<Account<'info, UserStore>>,
     #[account(
         mut,
         seeds = [
@@ -558,120 +3517,149 @@ pub struct SetCandyMachineRewardPerToken<'info> {
         bump = cm_reward_per_token.nonce,
     )]
     cm_reward_per_token: Box<Account<'info, CandyMachineRewardPerToken>>,
-    authority: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [
+            pool.to_account_info().key.as_ref(),
+            "reward_streams".as_bytes(),
+        ],
+        bump,
+    )]
+    /// CHECK: The pool's reward streams PDA. Pools that never ran `create_reward_streams` have none.
+    reward_streams: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        seeds = [
+            pool.to_account_info().key.as_ref(),
+            "set_bonus".as_bytes(),
+        ],
+        bump = set_bonus.nonce,
+    )]
+    set_bonus: Box<Account<'info, SetBonus>>,
+    #[account(mut)]
+    owner: Signer<'info>,
+
+    // Program signers.
+    #[account(
+        seeds = [
+            pool.to_account_info().key.as_ref()
+        ],
+        bump = pool.nonce,
+    )]
+    /// CHECK: This is pool signer with seeds
+    pool_signer: UncheckedAccount<'info>,
+
     // Misc.
+    token_program: Program<'info, Token>,
     system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-#[instruction(nonce: u8, store_nonce: u8)]
-pub struct CreateUser<'info> {
-    // Stake instance.
+pub struct SoftStake<'info> {
+    // Global accounts for the staking instance.
     #[account(
         mut,
-        constraint = !pool.paused,
+        has_one = vault,
     )]
     pool: Box<Account<'info, Pool>>,
-    // Member.
     #[account(
-        init,
-        payer = owner,
+        mut,
+    )]
+    vault: Box<Account<'info, Vault>>,
+
+    // User.
+    #[account(
+        mut, 
+        has_one = owner, 
+        has_one = pool,
         seeds = [
             owner.key.as_ref(), 
             pool.to_account_info().key.as_ref(),
             "user".as_bytes()
         ],
-        bump,
+        bump = user.nonce,
     )]
     user: Box<Account<'info, User>>,
+    // User Store.
     #[account(
-        init,
-        payer = owner,
+        mut, 
+        has_one = owner, 
         seeds = [
             owner.key.as_ref(), 
             pool.to_account_info().key.as_ref(),
             "user".as_bytes(),
-            &[1]
+            &[user_store.store_id]
         ],
-        bump,
-        space = 10240
+        bump = user_store.nonce,
     )]
     user_store: Box<Account<'info, UserStore>>,
-    #[account(mut)]
-    owner: Signer<'info>,
-    // Misc.
-    system_program: Program<'info, System>,
-}
-
-#[derive(Accounts)]
-#[instruction(nonce: u8)]
-pub struct CreateUserStore<'info> {
-    // Stake instance.
     #[account(
         mut,
-        constraint = !pool.paused,
+        seeds = [
+            pool.to_account_info().key.as_ref(),
+            "reward_per_token".as_bytes(),
+        ],
+        bump = cm_reward_per_token.nonce,
     )]
-    pool: Box<Account<'info, Pool>>,
-    // Member.
+    cm_reward_per_token: Box<Account<'info, CandyMachineRewardPerToken>>,
     #[account(
+        mut,
         seeds = [
-            owner.key.as_ref(), 
             pool.to_account_info().key.as_ref(),
-            "user".as_bytes(),
+            "reward_streams".as_bytes(),
         ],
-        bump = user.nonce,
+        bump,
     )]
-    user: Box<Account<'info, User>>,
+    /// CHECK: The pool's reward streams PDA. Pools that never ran `create_reward_streams` have none.
+    reward_streams: UncheckedAccount<'info>,
     #[account(
-        init,
-        payer = owner,
+        mut,
         seeds = [
-            owner.key.as_ref(), 
             pool.to_account_info().key.as_ref(),
-            "user".as_bytes(),
-            &[user.stores + 1]
+            "set_bonus".as_bytes(),
         ],
-        bump,
-        space = 10240
+        bump = set_bonus.nonce,
     )]
-    user_store: Box<Account<'info, UserStore>>,
+    set_bonus: Box<Account<'info, SetBonus>>,
     #[account(mut)]
     owner: Signer<'info>,
-    // Misc.
-    system_program: Program<'info, System>,
-}
-
-#[derive(Accounts)]
-pub struct Pause<'info> {
     #[account(
-        mut, 
-        has_one = authority,
-        constraint = !pool.paused,
+        mut,
+        constraint = stake_from_account.owner == owner.key(),
+        constraint = stake_from_account.mint == nft_mint.key() @ ErrorCode::InvalidNftMint,
     )]
-    pool: Box<Account<'info, Pool>>,
-    authority: Signer<'info>,
-
+    stake_from_account: Box<Account<'info, TokenAccount>>,
+    #[account(
+        constraint = nft_mint.supply == 1 @ ErrorCode::InvalidNftMint,
+        constraint = nft_mint.decimals == 0 @ ErrorCode::InvalidNftMint,
+    )]
+    nft_mint: Box<Account<'info, Mint>>,
     #[account(
+        owner = metaplex_token_metadata::id(),
         seeds = [
-            pool.to_account_info().key.as_ref()
+            metaplex_token_metadata::state::PREFIX.as_bytes(),
+            metaplex_token_metadata::id().as_ref(),
+            nft_mint.key().as_ref(),
         ],
-        bump = pool.nonce,
+        seeds::program = metaplex_token_metadata::id(),
+        bump,
     )]
-    /// CHECK: This is pool signer with seeds
-    pool_signer: UncheckedAccount<'info>,
-    token_program: Program<'info, Token>,
-}
-
-#[derive(Accounts)]
-pub struct Unpause<'info> {
+    /// CHECK: This is the metadata PDA of nft_mint, owned by the token metadata program.
+    metadata_info: UncheckedAccount<'info>,
     #[account(
-        mut, 
-        has_one = authority,
-        constraint = pool.paused,
+        seeds = [
+            metaplex_token_metadata::state::PREFIX.as_bytes(),
+            metaplex_token_metadata::id().as_ref(),
+            nft_mint.key().as_ref(),
+            metaplex_token_metadata::state::EDITION.as_bytes(),
+        ],
+        seeds::program = metaplex_token_metadata::id(),
+        bump,
     )]
-    pool: Box<Account<'info, Pool>>,
-    authority: Signer<'info>,
+    /// CHECK: This is the master edition PDA of nft_mint, checked by the token metadata program.
+    edition: UncheckedAccount<'info>,
 
+    // Program signers.
     #[account(
         seeds = [
             pool.to_account_info().key.as_ref()
@@ -680,29 +3668,70 @@ pub struct Unpause<'info> {
     )]
     /// CHECK: This is pool signer with seeds
     pool_signer: UncheckedAccount<'info>,
+
+    // Misc.
     token_program: Program<'info, Token>,
+    system_program: Program<'info, System>,
+    #[account(
+        address = metaplex_token_metadata::id(),
+    )]
+    /// CHECK: This is the token metadata program.
+    token_metadata_program: UncheckedAccount<'info>,
 }
 
 #[derive(Accounts)]
-pub struct DepositReward<'info> {
+pub struct EmergencyUnstake<'info> {
+    // Global accounts for the staking instance.
     #[account(
         mut,
-        constraint = reward_vault.owner == pool_signer.key(),
+        has_one = vault,
     )]
-    reward_vault: Box<Account<'info, TokenAccount>>,
+    pool: Box<Account<'info, Pool>>,
     #[account(
         mut,
     )]
-    reward_depositor: Box<Account<'info, TokenAccount>>,
-    reward_deposit_authority: Signer<'info>,
+    vault: Box<Account<'info, Vault>>,
+    #[account(
+        mut,
+        constraint = stake_to_account.owner == *pool_signer.key,
+    )]
+    stake_to_account: Box<Account<'info, TokenAccount>>,
 
+    // User.
     #[account(
         mut, 
-        has_one = authority,
+        has_one = owner, 
+        has_one = pool,
+        seeds = [
+            owner.key.as_ref(), 
+            pool.to_account_info().key.as_ref(),
+            "user".as_bytes()
+        ],
+        bump = user.nonce,
     )]
-    pool: Box<Account<'info, Pool>>,
-    authority: Signer<'info>,
+    user: Box<Account<'info, User>>,
+    // User Store.
+    #[account(
+        mut, 
+        has_one = owner, 
+        seeds = [
+            owner.key.as_ref(), 
+            pool.to_account_info().key.as_ref(),
+            "user".as_bytes(),
+            &[user_store.store_id]
+        ],
+        bump = user_store.nonce,
+    )]
+    user_store: Box<Account<'info, UserStore>>,
+    #[account(mut)]
+    owner: Signer<'info>,
+    #[account(
+        mut,
+        constraint = stake_from_account.mint == stake_to_account.mint @ ErrorCode::InvalidNftMint,
+    )]
+    stake_from_account: Box<Account<'info, TokenAccount>>,
 
+    // Program signers.
     #[account(
         seeds = [
             pool.to_account_info().key.as_ref()
@@ -711,25 +3740,24 @@ pub struct DepositReward<'info> {
     )]
     /// CHECK: This is pool signer with seeds
     pool_signer: UncheckedAccount<'info>,
+
+    // Misc.
     token_program: Program<'info, Token>,
+    system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-pub struct Stake<'info> {
+pub struct EmergencySoftUnstake<'info> {
     // Global accounts for the staking instance.
     #[account(
         mut,
+        has_one = vault,
     )]
     pool: Box<Account<'info, Pool>>,
     #[account(
         mut,
     )]
     vault: Box<Account<'info, Vault>>,
-    #[account(
-        mut,
-        constraint = stake_to_account.owner == *pool_signer.key,
-    )]
-    stake_to_account: Box<Account<'info, TokenAccount>>,
 
     // User.
     #[account(
@@ -757,20 +3785,27 @@ pub struct Stake<'info> {
         bump = user_store.nonce,
     )]
     user_store: Box<Account<'info, UserStore>>,
+    #[account(mut)]
+    owner: Signer<'info>,
     #[account(
         mut,
+        constraint = stake_from_account.owner == owner.key(),
+        constraint = stake_from_account.mint == nft_mint.key() @ ErrorCode::InvalidNftMint,
+    )]
+    stake_from_account: Box<Account<'info, TokenAccount>>,
+    nft_mint: Box<Account<'info, Mint>>,
+    #[account(
         seeds = [
-            pool.to_account_info().key.as_ref(),
-            "reward_per_token".as_bytes(),
+            metaplex_token_metadata::state::PREFIX.as_bytes(),
+            metaplex_token_metadata::id().as_ref(),
+            nft_mint.key().as_ref(),
+            metaplex_token_metadata::state::EDITION.as_bytes(),
         ],
-        bump = cm_reward_per_token.nonce,
+        seeds::program = metaplex_token_metadata::id(),
+        bump,
     )]
-    cm_reward_per_token: Box<Account<'info, CandyMachineRewardPerToken>>,
-    owner: Signer<'info>,
-    #[account(mut)]
-    stake_from_account: Box<Account<'info, TokenAccount>>,
-    /// CHECK: This is nft metadata account. 
-    metadata_info: UncheckedAccount<'info>,
+    /// CHECK: This is the master edition PDA of nft_mint, checked by the token metadata program.
+    edition: UncheckedAccount<'info>,
 
     // Program signers.
     #[account(
@@ -784,6 +3819,12 @@ pub struct Stake<'info> {
 
     // Misc.
     token_program: Program<'info, Token>,
+    system_program: Program<'info, System>,
+    #[account(
+        address = metaplex_token_metadata::id(),
+    )]
+    /// CHECK: This is the token metadata program.
+    token_metadata_program: UncheckedAccount<'info>,
 }
 
 #[derive(Accounts)]
@@ -792,6 +3833,7 @@ pub struct ClaimReward<'info> {
     #[account(
         mut, 
         has_one = reward_vault,
+        has_one = vault,
     )]
     pool: Box<Account<'info, Pool>>,
     #[account(
@@ -823,6 +3865,25 @@ pub struct ClaimReward<'info> {
         bump = cm_reward_per_token.nonce,
     )]
     cm_reward_per_token: Box<Account<'info, CandyMachineRewardPerToken>>,
+    #[account(
+        mut,
+        seeds = [
+            pool.to_account_info().key.as_ref(),
+            "reward_streams".as_bytes(),
+        ],
+        bump,
+    )]
+    /// CHECK: The pool's reward streams PDA. Pools that never ran `create_reward_streams` have none.
+    reward_streams: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        seeds = [
+            pool.to_account_info().key.as_ref(),
+            "set_bonus".as_bytes(),
+        ],
+        bump = set_bonus.nonce,
+    )]
+    set_bonus: Box<Account<'info, SetBonus>>,
     // User Store.
     #[account(
         mut,
@@ -836,6 +3897,7 @@ pub struct ClaimReward<'info> {
         bump = user_store.nonce,
     )]
     user_store: Box<Account<'info, UserStore>>,
+    #[account(mut)]
     owner: Signer<'info>,
     #[account(mut)]
     reward_account: Box<Account<'info, TokenAccount>>,
@@ -852,26 +3914,35 @@ pub struct ClaimReward<'info> {
 
     // Misc.
     token_program: Program<'info, Token>,
+    system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-pub struct WithdrawReward<'info> {
+pub struct ExecuteWithdrawReward<'info> {
     // Global accounts for the staking instance.
     #[account(
         mut, 
         has_one = reward_vault,
-        constraint = pool.authority == *owner.key
+        constraint = pool.pause_flags & PAUSE_ADMIN == 0 @ ErrorCode::AdminPaused,
     )]
     pool: Box<Account<'info, Pool>>,
     #[account(
         mut
     )]
     reward_vault: Box<Account<'info, TokenAccount>>,
-
-    owner: Signer<'info>,
     #[account(
         mut,
-        constraint = reward_account.owner == *owner.key
+        close = proposer,
+        has_one = pool,
+        has_one = proposer,
+    )]
+    pending_action: Box<Account<'info, PendingAction>>,
+    #[account(mut)]
+    /// CHECK: Gets the pending action's rent back; checked by has_one.
+    proposer: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        constraint = reward_account.key() == pending_action.reward_account @ ErrorCode::Unauthorized,
     )]
     reward_account: Box<Account<'info, TokenAccount>>,
 
@@ -914,88 +3985,267 @@ pub struct CloseUser<'info> {
     pool: Box<Account<'info, Pool>>,
     #[account(
         mut,
-        close = owner,
-        has_one = owner,
-        has_one = pool,
+        close = owner,
+        has_one = owner,
+        has_one = pool,
+        seeds = [
+            owner.to_account_info().key.as_ref(),
+            pool.to_account_info().key.as_ref(),
+            "user".as_bytes()
+        ],
+        bump = user.nonce,
+        constraint = user.balance_staked == 0,
+        constraint = user.stores == 0,
+    )]
+    user: Account<'info, User>,
+    owner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CloseUserStore<'info> {
+    pool: Box<Account<'info, Pool>>,
+    #[account(
+        mut, 
+        has_one = owner,
+        has_one = pool,
+        seeds = [
+            owner.to_account_info().key.as_ref(),
+            pool.to_account_info().key.as_ref(),
+            "user".as_bytes()
+        ],
+        bump = user.nonce,
+    )]
+    user: Box<Account<'info, User>>,
+    #[account(
+        mut,
+        close = owner,
+        has_one = owner,
+        seeds = [
+            owner.to_account_info().key.as_ref(),
+            pool.to_account_info().key.as_ref(),
+            "user".as_bytes(),
+            &[user_store.store_id]
+        ],
+        bump = user_store.nonce,
+        constraint = user_store.nft_mints.is_empty() @ ErrorCode::StoreNotEmpty,
+        constraint = user_store.reward_token_pending == 0 @ ErrorCode::RewardsPending,
+        constraint = user_store.stream_rewards_pending.iter().all(|&x| x < REWARD_PRECISION) @ ErrorCode::RewardsPending,
+    )]
+    user_store: Account<'info, UserStore>,
+    owner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ManageCandyMachine<'info> {
+    // Stake instance.
+    #[account(
+        mut,
+        constraint = pool.collection_manager == authority.key() @ ErrorCode::Unauthorized,
+        has_one = vault,
+        constraint = pool.pause_flags & PAUSE_ADMIN == 0 @ ErrorCode::AdminPaused,
+    )]
+    pool: Box<Account<'info, Pool>>,
+    #[account(
+        mut,
+    )]
+    vault: Box<Account<'info, Vault>>,
+    #[account(
+        seeds = [
+            pool.to_account_info().key.as_ref(),
+            "reward_per_token".as_bytes(),
+        ],
+        bump = cm_reward_per_token.nonce,
+    )]
+    cm_reward_per_token: Box<Account<'info, CandyMachineRewardPerToken>>,
+    authority: Signer<'info>,
+    // Misc.
+    system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RemoveCandyMachine<'info> {
+    // Stake instance.
+    #[account(
+        mut,
+        constraint = pool.collection_manager == authority.key() @ ErrorCode::Unauthorized,
+        has_one = vault,
+        constraint = pool.pause_flags & PAUSE_ADMIN == 0 @ ErrorCode::AdminPaused,
+    )]
+    pool: Box<Account<'info, Pool>>,
+    #[account(
+        mut,
+    )]
+    vault: Box<Account<'info, Vault>>,
+    #[account(
         seeds = [
-            owner.to_account_info().key.as_ref(),
             pool.to_account_info().key.as_ref(),
-            "user".as_bytes()
+            "reward_per_token".as_bytes(),
         ],
-        bump = user.nonce,
-        constraint = user.balance_staked == 0,
-        constraint = user.stores == 0,
+        bump = cm_reward_per_token.nonce,
     )]
-    user: Account<'info, User>,
-    owner: Signer<'info>,
+    cm_reward_per_token: Box<Account<'info, CandyMachineRewardPerToken>>,
+    #[account(
+        mut,
+        close = proposer,
+        has_one = pool,
+        has_one = proposer,
+    )]
+    pending_action: Box<Account<'info, PendingAction>>,
+    #[account(mut)]
+    /// CHECK: Gets the pending action's rent back; checked by has_one.
+    proposer: UncheckedAccount<'info>,
+    authority: Signer<'info>,
 }
 
 #[derive(Accounts)]
-pub struct CloseUserStore<'info> {
+pub struct SetEarlyUnstakePenalty<'info> {
+    // Stake instance.
+    #[account(
+        constraint = pool.collection_manager == authority.key() @ ErrorCode::Unauthorized,
+        has_one = vault,
+        constraint = pool.pause_flags & PAUSE_ADMIN == 0 @ ErrorCode::AdminPaused,
+    )]
     pool: Box<Account<'info, Pool>>,
     #[account(
-        mut, 
+        mut,
     )]
-    user: Box<Account<'info, User>>,
+    vault: Box<Account<'info, Vault>>,
     #[account(
         mut,
-        close = owner,
-        has_one = owner,
-        seeds = [
-            owner.to_account_info().key.as_ref(),
-            pool.to_account_info().key.as_ref(),
-            "user".as_bytes(),
-            &[user_store.store_id]
-        ],
-        bump = user_store.nonce,
-        constraint = user_store.reward_token_pending == 0,
+        close = proposer,
+        has_one = pool,
+        has_one = proposer,
     )]
-    user_store: Account<'info, UserStore>,
-    owner: Signer<'info>,
+    pending_action: Box<Account<'info, PendingAction>>,
+    #[account(mut)]
+    /// CHECK: Gets the pending action's rent back; checked by has_one.
+    proposer: UncheckedAccount<'info>,
+    authority: Signer<'info>,
 }
 
 #[derive(Accounts)]
-pub struct ManageCandyMachine<'info> {
+pub struct ManageVaultRates<'info> {
     // Stake instance.
     #[account(
         mut,
-        has_one = authority,
-        constraint = !pool.paused,
+        constraint = pool.rate_manager == authority.key() @ ErrorCode::Unauthorized,
+        has_one = vault,
+        constraint = pool.pause_flags & PAUSE_ADMIN == 0 @ ErrorCode::AdminPaused,
     )]
     pool: Box<Account<'info, Pool>>,
     #[account(
         mut,
     )]
     vault: Box<Account<'info, Vault>>,
+    #[account(
+        mut,
+        close = proposer,
+        has_one = pool,
+        has_one = proposer,
+    )]
+    pending_action: Box<Account<'info, PendingAction>>,
+    #[account(mut)]
+    /// CHECK: Gets the pending action's rent back; checked by has_one.
+    proposer: UncheckedAccount<'info>,
     authority: Signer<'info>,
     // Misc.
     system_program: Program<'info, System>,
 }
 
+#[derive(Accounts)]
+pub struct ReportSolvency<'info> {
+    #[account(
+        has_one = reward_vault,
+        has_one = vault,
+    )]
+    pool: Box<Account<'info, Pool>>,
+    vault: Box<Account<'info, Vault>>,
+    #[account(
+        seeds = [
+            pool.to_account_info().key.as_ref(),
+            "reward_per_token".as_bytes(),
+        ],
+        bump = cm_reward_per_token.nonce,
+    )]
+    cm_reward_per_token: Box<Account<'info, CandyMachineRewardPerToken>>,
+    reward_vault: Box<Account<'info, TokenAccount>>,
+}
+
 #[account]
+#[derive(Default)]
 pub struct Pool {
     /// Priviledged account.
     pub authority: Pubkey,
     /// Nonce to derive the program-derived address owning the vaults.
     pub nonce: u8,
-    /// Paused state of the program
-    pub paused: bool,
+    /// PAUSE_* flags of the flows currently paused.
+    pub pause_flags: u8,
     /// Mint of the reward token.
     pub reward_mint: Pubkey,
     /// Vault to store reward tokens.
     pub reward_vault: Pubkey,
+    /// Allowlist of candy machines and collections.
+    pub vault: Pubkey,
     /// Rate of reward distribution.
     pub reward_per_token: u64,
     /// Users staked
     pub user_stake_count: u32,
     pub balance_staked: u64,
+    /// Rewards accrued to stakers and not yet paid out, scaled by REWARD_PRECISION.
+    pub reward_liability: u128,
+    /// Window in which the base rates are emitted.
+    pub emission_start_time: u64,
+    pub emission_end_time: u64,
+    /// Seconds between emission steps, 0 for a flat rate.
+    pub emission_step_interval: u64,
+    /// Share of the previous rate kept at each step, in basis points (5000 halves it).
+    pub emission_step_bps: u16,
+    /// Merkle root of (mint, tier) pairs assigning rarity tiers.
+    pub rarity_root: [u8; 32],
+    /// Authority nominated by `propose_authority`, or the default key if none.
+    pub pending_authority: Pubkey,
+    /// Sets reward rates, schedules, streams, multipliers and the set bonus.
+    pub rate_manager: Pubkey,
+    /// Adds and removes candy machines and sets their lock penalties.
+    pub collection_manager: Pubkey,
+    /// Can pause the pool; only the authority can unpause it.
+    pub pauser: Pubkey,
+    /// Deposits and withdraws reward tokens.
+    pub treasurer: Pubkey,
+    /// Seconds a queued rate change or withdrawal waits before it can run.
+    pub timelock_delay: u64,
+    /// Id of the next queued action.
+    pub next_action_id: u64,
 }
 
 #[account]
+#[derive(Default)]
 pub struct Vault {
+    /// Verified creator (candy machine) or verified collection keys eligible for staking.
     pub candy_machines: Vec<Pubkey>,
+    /// Lock period in days for NFTs of each candy machine.
     pub reward_types: Vec<u8>,
     pub nonce: u8,
+    /// Rewards accrued per staked NFT of each candy machine, scaled by REWARD_PRECISION.
+    pub acc_reward_per_shares: Vec<u128>,
+    /// Last time the accumulators were brought up to date.
+    pub last_update_time: u64,
+    /// Number of NFTs staked in the pool per candy machine.
+    pub staked_counts: Vec<u32>,
+    /// Share of pending rewards forfeited to unstake before the lock ends, in basis points. 0 is a hard lock.
+    pub early_unstake_penalty_bps: Vec<u16>,
+    /// Sum of the reward weights staked per candy machine, in basis points per NFT.
+    pub staked_weights: Vec<u64>,
+    /// Days staked after which each loyalty multiplier applies, ascending.
+    pub loyalty_days: Vec<u16>,
+    /// Reward multiplier of each loyalty step, in basis points (12500 is 1.25x).
+    pub loyalty_multiplier_bps: Vec<u16>,
+    /// Reward multiplier of each rarity tier, in basis points, indexed by tier.
+    pub tier_multiplier_bps: Vec<u16>,
+    /// Whether each candy machine was removed. Removed entries stop accruing and are dropped once empty.
+    pub retired: Vec<bool>,
+    /// Last time the loyalty or tier multipliers changed.
+    pub multipliers_updated_at: u64,
 }
 
 #[account]
@@ -1005,6 +4255,71 @@ pub struct CandyMachineRewardPerToken {
     pub nonce: u8,
 }
 
+#[account]
+pub struct RewardStreams {
+    /// Extra rewards paid alongside the pool's own reward mint.
+    pub streams: Vec<RewardStream>,
+    pub nonce: u8,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default)]
+pub struct RewardStream {
+    pub reward_mint: Pubkey,
+    /// Pool-signer owned vault the stream pays from.
+    pub reward_vault: Pubkey,
+    /// Daily reward per staked NFT.
+    pub reward_per_token: u64,
+    /// Window the stream accrues in.
+    pub start_time: u64,
+    pub end_time: u64,
+    /// Rewards accrued per staked NFT, scaled by REWARD_PRECISION.
+    pub acc_reward_per_share: u128,
+    /// Last time the accumulator was brought up to date.
+    pub last_update_time: u64,
+}
+
+#[account]
+#[derive(Default)]
+pub struct PendingAction {
+    pub pool: Pubkey,
+    /// Sequence number of the action within the pool.
+    pub id: u64,
+    /// Signer that queued the action; gets the account rent back.
+    pub proposer: Pubkey,
+    /// One of the ACTION_* kinds.
+    pub kind: u8,
+    /// New rate, withdrawal amount or new delay, depending on the kind.
+    pub value: u64,
+    /// Token account a withdrawal pays into.
+    pub reward_account: Pubkey,
+    /// Borsh-encoded arguments the gated instruction must be called with.
+    pub args: Vec<u8>,
+    /// Earliest time the action can run.
+    pub eta: u64,
+    pub nonce: u8,
+}
+
+#[account]
+pub struct SetBonus {
+    /// Candy machines a user needs at least one NFT staked from, each, to earn the bonus.
+    pub candy_machines: Vec<Pubkey>,
+    /// Extra reward while the set is complete, in basis points of normal accrual.
+    pub bonus_bps: u16,
+    pub nonce: u8,
+    /// Bonus paid to a complete set since the pool started, in basis points times emitted time.
+    pub acc_bonus_bps: u128,
+    /// Last time the accumulator was brought up to date.
+    pub last_update_time: u64,
+    /// Candy machines of the set before its last change.
+    pub previous_candy_machines: Vec<Pubkey>,
+    /// Accumulator value when the set last changed.
+    pub set_changed_acc_bonus_bps: u128,
+    /// Number of times the set changed.
+    pub set_changes: u64,
+    /// Emitted time since the pool started, each interval weighted by the schedule in force then.
+    pub acc_emitted_time: u128,
+}
+
 #[account]
 #[derive(Default)]
 pub struct User {
@@ -1016,9 +4331,22 @@ pub struct User {
     pub last_update_time: u64,
     /// The amount staked.
     pub balance_staked: u64,
-    /// 
+    /// Signer nonce.
     pub nonce: u8,
+    /// Number of open user stores.
     pub stores: u8,
+    /// Id of the most recently created user store.
+    pub last_store_id: u8,
+    /// Candy machines this user has NFTs staked from, across all stores.
+    pub candy_machines: Vec<Pubkey>,
+    /// Number of NFTs staked per candy machine, across all stores.
+    pub staked_counts: Vec<u32>,
+    /// Set bonus accumulator at the user's last update.
+    pub bonus_checkpoint: u128,
+    /// Set changes seen at the user's last update.
+    pub set_bonus_changes: u64,
+    /// Set bonus earned since the user was created, in basis points times emitted time.
+    pub earned_bonus_bps: u128,
 }
 
 #[account]
@@ -1029,17 +4357,535 @@ pub struct UserStore {
     pub nonce: u8,
     /// NFT mints stacked
     pub nft_mints: Vec<Pubkey>,
+    /// Candy machine each staked NFT was matched against.
+    pub nft_candy_machines: Vec<Pubkey>,
+    /// Lock period in days of each staked NFT.
     pub types: Vec<u8>,
+    /// Rarity tier of each staked NFT.
+    pub tiers: Vec<u8>,
+    /// Time each NFT was staked.
     pub staked_times: Vec<u64>,
+    /// Time from which each staked NFT can be unstaked.
+    pub unlock_times: Vec<u64>,
+    /// Whether each NFT is frozen in the owner's wallet rather than held by the pool.
+    pub soft_staked: Vec<bool>,
     pub store_id: u8,
-    /// The amount of token pending claim.
+    /// The amount of token pending claim, including any the reward vault couldn't pay out yet.
     pub reward_token_pending: u64,
+    /// Fraction of a token accrued but not yet pending, scaled by REWARD_PRECISION.
+    pub reward_remainder: u128,
+    /// Candy machines this store holds NFTs from.
+    pub candy_machines: Vec<Pubkey>,
+    /// Number of NFTs staked per candy machine.
+    pub staked_counts: Vec<u32>,
+    /// Accumulator share already credited per candy machine, scaled by REWARD_PRECISION and BPS.
+    pub reward_debts: Vec<u128>,
+    /// Reward weight each staked NFT currently earns at, in basis points.
+    pub nft_weights: Vec<u64>,
+    /// Sum of the reward weights staked per candy machine, in basis points per NFT.
+    pub staked_weights: Vec<u64>,
+    /// Accumulator value each reward stream was last settled at, scaled by REWARD_PRECISION.
+    pub stream_checkpoints: Vec<u128>,
+    /// Rewards pending claim per reward stream, scaled by REWARD_PRECISION.
+    pub stream_rewards_pending: Vec<u128>,
+    /// Accumulator of each NFT's candy machine when it was staked, scaled by REWARD_PRECISION.
+    pub entry_reward_per_shares: Vec<u128>,
+    /// Last time the store's rewards were settled.
+    pub last_update_time: u64,
+    /// Earliest time a staked NFT reaches its next loyalty step; u64::MAX when none will.
+    pub next_weight_update: u64,
+    /// Owner's earned set bonus at the store's last update.
+    pub bonus_checkpoint: u128,
+    /// Set bonus emitted time accumulator at the store's last update.
+    pub emitted_checkpoint: u128,
+    /// Sum of the weights of still-locked NFTs per candy machine, in basis points per NFT.
+    pub locked_weights: Vec<u64>,
+    /// Sum of weight times entry accumulator of still-locked NFTs per candy machine.
+    pub locked_entry_shares: Vec<u128>,
+    /// Earliest unlock time of an NFT the locked sums still count; u64::MAX when none.
+    pub next_unlock_time: u64,
+    /// Reward tokens the store's locked NFTs would forfeit by unstaking early, as of its last update.
+    pub withheld: u64,
+}
+
+#[event]
+pub struct PoolInitialized {
+    pub pool: Pubkey,
+    pub authority: Pubkey,
+    pub reward_mint: Pubkey,
+    pub timestamp: u64,
+}
+
+#[event]
+pub struct AuthorityProposed {
+    pub pool: Pubkey,
+    pub authority: Pubkey,
+    pub pending_authority: Pubkey,
+    pub timestamp: u64,
+}
+
+#[event]
+pub struct AuthorityChanged {
+    pub pool: Pubkey,
+    pub old_authority: Pubkey,
+    pub new_authority: Pubkey,
+    pub timestamp: u64,
+}
+
+#[event]
+pub struct RolesChanged {
+    pub pool: Pubkey,
+    pub rate_manager: Pubkey,
+    pub collection_manager: Pubkey,
+    pub pauser: Pubkey,
+    pub treasurer: Pubkey,
+    pub timestamp: u64,
+}
+
+#[event]
+pub struct ActionQueued {
+    pub pool: Pubkey,
+    pub id: u64,
+    pub kind: u8,
+    pub value: u64,
+    pub args: Vec<u8>,
+    pub eta: u64,
+    pub timestamp: u64,
+}
+
+#[event]
+pub struct ActionExecuted {
+    pub pool: Pubkey,
+    pub id: u64,
+    pub kind: u8,
+    pub value: u64,
+    pub timestamp: u64,
+}
+
+#[event]
+pub struct ActionCancelled {
+    pub pool: Pubkey,
+    pub id: u64,
+    pub kind: u8,
+    pub timestamp: u64,
+}
+
+#[event]
+pub struct TimelockDelayChanged {
+    pub pool: Pubkey,
+    pub timelock_delay: u64,
+    pub timestamp: u64,
+}
+
+#[event]
+pub struct CandyMachineRewardPerTokenCreated {
+    pub pool: Pubkey,
+    pub authority: Pubkey,
+    pub timestamp: u64,
+}
+
+#[event]
+pub struct RewardRateChanged {
+    pub pool: Pubkey,
+    /// None for the pool-wide rate.
+    pub candy_machine: Option<Pubkey>,
+    pub reward_per_token: u64,
+    pub timestamp: u64,
+}
+
+#[event]
+pub struct EmissionScheduleChanged {
+    pub pool: Pubkey,
+    pub start_time: u64,
+    pub end_time: u64,
+    pub step_interval: u64,
+    pub step_bps: u16,
+    pub timestamp: u64,
+}
+
+#[event]
+pub struct RewardStreamsCreated {
+    pub pool: Pubkey,
+    pub authority: Pubkey,
+    pub timestamp: u64,
+}
+
+#[event]
+pub struct RewardStreamChanged {
+    pub pool: Pubkey,
+    pub index: u8,
+    pub reward_mint: Pubkey,
+    pub reward_per_token: u64,
+    pub start_time: u64,
+    pub end_time: u64,
+    pub timestamp: u64,
+}
+
+#[event]
+pub struct CandyMachineAdded {
+    pub pool: Pubkey,
+    pub candy_machine: Pubkey,
+    pub reward_type: u8,
+    pub timestamp: u64,
+}
+
+#[event]
+pub struct EarlyUnstakePenaltyChanged {
+    pub pool: Pubkey,
+    pub candy_machine: Pubkey,
+    pub penalty_bps: u16,
+    pub timestamp: u64,
+}
+
+#[event]
+pub struct EarlyUnstakePenalty {
+    pub pool: Pubkey,
+    pub owner: Pubkey,
+    pub store_id: u8,
+    pub mint: Pubkey,
+    /// Pending rewards returned to the reward vault.
+    pub forfeited: u64,
+    pub timestamp: u64,
+}
+
+#[event]
+pub struct LoyaltyMultipliersChanged {
+    pub pool: Pubkey,
+    pub days: Vec<u16>,
+    pub multiplier_bps: Vec<u16>,
+    pub timestamp: u64,
+}
+
+#[event]
+pub struct RarityRootChanged {
+    pub pool: Pubkey,
+    pub rarity_root: [u8; 32],
+    pub timestamp: u64,
+}
+
+#[event]
+pub struct TierMultipliersChanged {
+    pub pool: Pubkey,
+    pub multiplier_bps: Vec<u16>,
+    pub timestamp: u64,
+}
+
+#[event]
+pub struct SetBonusCreated {
+    pub pool: Pubkey,
+    pub authority: Pubkey,
+    pub timestamp: u64,
+}
+
+#[event]
+pub struct SetBonusChanged {
+    pub pool: Pubkey,
+    pub candy_machines: Vec<Pubkey>,
+    pub bonus_bps: u16,
+    pub timestamp: u64,
+}
+
+#[event]
+pub struct CandyMachineRemoved {
+    pub pool: Pubkey,
+    pub candy_machine: Pubkey,
+    pub timestamp: u64,
+}
+
+#[event]
+pub struct Paused {
+    pub pool: Pubkey,
+    pub pause_flags: u8,
+    pub timestamp: u64,
+}
+
+#[event]
+pub struct Unpaused {
+    pub pool: Pubkey,
+    pub pause_flags: u8,
+    pub timestamp: u64,
+}
+
+#[event]
+pub struct UserCreated {
+    pub pool: Pubkey,
+    pub owner: Pubkey,
+    pub timestamp: u64,
+}
+
+#[event]
+pub struct UserStoreCreated {
+    pub pool: Pubkey,
+    pub owner: Pubkey,
+    pub store_id: u8,
+    pub timestamp: u64,
+}
+
+#[event]
+pub struct UserStoreClosed {
+    pub pool: Pubkey,
+    pub owner: Pubkey,
+    pub store_id: u8,
+    pub timestamp: u64,
+}
+
+#[event]
+pub struct UserClosed {
+    pub pool: Pubkey,
+    pub owner: Pubkey,
+    pub timestamp: u64,
+}
+
+#[event]
+pub struct Staked {
+    pub pool: Pubkey,
+    pub owner: Pubkey,
+    pub store_id: u8,
+    pub mint: Pubkey,
+    pub candy_machine: Pubkey,
+    pub tier: u8,
+    pub unlock_time: u64,
+    pub soft_staked: bool,
+    pub timestamp: u64,
+}
+
+#[event]
+pub struct Unstaked {
+    pub pool: Pubkey,
+    pub owner: Pubkey,
+    pub store_id: u8,
+    pub mint: Pubkey,
+    pub candy_machine: Pubkey,
+    pub soft_staked: bool,
+    pub timestamp: u64,
+}
+
+#[event]
+pub struct EmergencyUnstaked {
+    pub pool: Pubkey,
+    pub owner: Pubkey,
+    pub store_id: u8,
+    pub mint: Pubkey,
+    pub candy_machine: Pubkey,
+    pub soft_staked: bool,
+    pub timestamp: u64,
+}
+
+#[event]
+pub struct Claimed {
+    pub pool: Pubkey,
+    pub owner: Pubkey,
+    pub store_id: u8,
+    pub reward_mint: Pubkey,
+    pub amount: u64,
+    pub timestamp: u64,
+}
+
+#[event]
+pub struct RewardDeposited {
+    pub pool: Pubkey,
+    pub depositor: Pubkey,
+    pub amount: u64,
+    pub timestamp: u64,
+}
+
+#[event]
+pub struct RewardWithdrawn {
+    pub pool: Pubkey,
+    pub reward_account: Pubkey,
+    pub amount: u64,
+    pub timestamp: u64,
+}
+
+#[event]
+pub struct TokenAccountClosed {
+    pub pool: Pubkey,
+    pub account: Pubkey,
+    pub timestamp: u64,
+}
+
+#[event]
+pub struct ClaimShortfall {
+    pub pool: Pubkey,
+    pub owner: Pubkey,
+    pub store_id: u8,
+    pub reward_mint: Pubkey,
+    /// Amount transferred by this claim.
+    pub paid: u64,
+    /// Amount left pending because the reward vault ran dry.
+    pub unpaid: u64,
+    pub timestamp: u64,
+}
+
+#[event]
+pub struct SolvencyReport {
+    pub pool: Pubkey,
+    pub reward_vault_balance: u64,
+    /// Rewards owed to stakers, settled or not.
+    pub reward_liability: u64,
+    /// Rewards emitted per day by everything currently staked.
+    pub emission_per_day: u64,
+    /// Seconds until the reward vault can't cover the liability at the current emission.
+    pub runway: u64,
+    pub timestamp: u64,
 }
 
 #[error_code]
 pub enum ErrorCode {
-    #[msg("Pool is paused.")]
-    PoolPaused,
+    #[msg("Staking is paused.")]
+    StakingPaused,
+    #[msg("Claims are paused.")]
+    ClaimsPaused,
+    #[msg("Admin changes are paused.")]
+    AdminPaused,
+    #[msg("Unknown pause flags.")]
+    InvalidPauseFlags,
     #[msg("Candy machine not found.")]
     CandyNotMatch,
+    #[msg("NFT is still locked.")]
+    StakeLocked,
+    #[msg("Token is not an NFT or doesn't match the staked mint.")]
+    InvalidNftMint,
+    #[msg("Math operation overflowed.")]
+    MathOverflow,
+    #[msg("NFT is not staked in this store.")]
+    NftNotStaked,
+    #[msg("User store is full.")]
+    StoreFull,
+    #[msg("Invalid timestamp.")]
+    InvalidTimestamp,
+    #[msg("User store still has NFTs staked.")]
+    StoreNotEmpty,
+    #[msg("Claim pending rewards first.")]
+    RewardsPending,
+    #[msg("NFT was staked in the other staking mode.")]
+    WrongStakeMode,
+    #[msg("Remaining accounts don't form valid per-NFT groups.")]
+    InvalidRemainingAccounts,
+    #[msg("Pool already has the most reward streams allowed.")]
+    TooManyRewardStreams,
+    #[msg("Reward stream not found.")]
+    RewardStreamNotFound,
+    #[msg("Emission schedule must end after it starts and only step down.")]
+    InvalidEmissionSchedule,
+    #[msg("Penalty can't exceed 10000 basis points.")]
+    InvalidPenalty,
+    #[msg("Loyalty steps must pair each day count with a multiplier, in ascending order of days.")]
+    InvalidLoyaltyMultipliers,
+    #[msg("Rarity tier proof doesn't match the pool's rarity root.")]
+    InvalidRarityProof,
+    #[msg("Too many rarity tiers.")]
+    InvalidTierMultipliers,
+    #[msg("Too many candy machines.")]
+    TooManyCandyMachines,
+    #[msg("Signer is not the pending pool authority.")]
+    NotPendingAuthority,
+    #[msg("Signer doesn't hold the role this instruction needs.")]
+    Unauthorized,
+    #[msg("Queued action can't run before its eta.")]
+    ActionNotReady,
+    #[msg("Queued action is of a different kind.")]
+    WrongActionKind,
+    #[msg("Shorter timelock delays must be queued.")]
+    TimelockDecrease,
+    #[msg("Arguments don't match the queued action.")]
+    ActionArgsMismatch,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use metaplex_token_metadata::state::{Creator, Data, Key, MAX_METADATA_LEN};
+
+    /// Metadata account data listing `creators` and, if any, `collection`, each with whether it's verified.
+    fn metadata_data(creators: Vec<(Pubkey, bool)>, collection: Option<(Pubkey, bool)>) -> Vec<u8> {
+        let metadata = Metadata {
+            key: Key::MetadataV1,
+            update_authority: Pubkey::new_unique(),
+            mint: Pubkey::new_unique(),
+            data: Data {
+                name: String::new(),
+                symbol: String::new(),
+                uri: String::new(),
+                seller_fee_basis_points: 0,
+                creators: Some(creators.into_iter().map(|(address, verified)| Creator { address, verified, share: 100 }).collect()),
+            },
+            primary_sale_happened: false,
+            is_mutable: true,
+            edition_nonce: None,
+        };
+        let mut data = metadata.try_to_vec().unwrap();
+        // No token standard, then the optional collection.
+        data.push(0);
+        if let Some((key, verified)) = collection {
+            data.push(1);
+            data.push(verified as u8);
+            data.extend_from_slice(key.as_ref());
+        }
+        data.resize(MAX_METADATA_LEN, 0);
+        data
+    }
+
+    /// Vault index `find_candy_machine` picks for metadata account `data`.
+    fn candy_machine_index(vault: &Vault, mut data: Vec<u8>) -> Result<usize> {
+        let (key, owner, mut lamports) = (Pubkey::new_unique(), metaplex_token_metadata::id(), 0);
+        let metadata_info = AccountInfo::new(&key, false, false, &mut lamports, &mut data, &owner, false, 0);
+        find_candy_machine(vault, &metadata_info)
+    }
+
+    #[test]
+    fn only_verified_creators_and_collections_qualify() {
+        let (candy_machine, collection) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let vault = Vault {
+            candy_machines: vec![candy_machine, collection],
+            retired: vec![false, false],
+            ..Vault::default()
+        };
+
+        assert_eq!(candy_machine_index(&vault, metadata_data(vec![(candy_machine, true)], None)).unwrap(), 0);
+        assert!(candy_machine_index(&vault, metadata_data(vec![(candy_machine, false)], None)).is_err());
+
+        assert_eq!(candy_machine_index(&vault, metadata_data(vec![], Some((collection, true)))).unwrap(), 1);
+        assert!(candy_machine_index(&vault, metadata_data(vec![], Some((collection, false)))).is_err());
+        // A verified collection the vault doesn't list doesn't qualify either.
+        assert!(candy_machine_index(&vault, metadata_data(vec![], Some((Pubkey::new_unique(), true)))).is_err());
+    }
+
+    // Loyalty steps are whole days apart, too far for the integration tests to wait out.
+    #[test]
+    fn loyalty_step_weight_applies_from_the_step() {
+        let candy_machine = Pubkey::new_unique();
+        let pool = Pool { emission_end_time: u64::MAX, ..Pool::default() };
+        let vault = Vault {
+            candy_machines: vec![candy_machine],
+            acc_reward_per_shares: vec![5_000],
+            loyalty_days: vec![1, 2],
+            loyalty_multiplier_bps: vec![20000, 15000],
+            ..Vault::default()
+        };
+        // Staked at 1x at time 0 and last updated an hour before the 1 day step, when the accumulator was 1000.
+        let mut user_store = UserStore {
+            nft_mints: vec![Pubkey::new_unique()],
+            nft_candy_machines: vec![candy_machine],
+            candy_machines: vec![candy_machine],
+            tiers: vec![0],
+            staked_times: vec![0],
+            nft_weights: vec![BPS],
+            staked_weights: vec![BPS],
+            reward_debts: vec![BPS as u128 * 1_000],
+            last_update_time: SECONDS_PER_DAY - 3600,
+            ..UserStore::default()
+        };
+
+        // Three of the four hours accrued since are past the step, so they earn 2x instead of 1x.
+        let current_time = SECONDS_PER_DAY + 3 * 3600;
+        let (gained, lost) = loyalty_step_adjustment(&pool, &user_store, &vault, current_time).unwrap();
+        assert_eq!((gained, lost), (BPS as u128 * 3_000, 0));
+
+        // Over both steps: 2x for the last 36 of 48 hours, then down to 1.5x for the last 12.
+        user_store.last_update_time = SECONDS_PER_DAY / 2;
+        let current_time = 2 * SECONDS_PER_DAY + SECONDS_PER_DAY / 2;
+        let (gained, lost) = loyalty_step_adjustment(&pool, &user_store, &vault, current_time).unwrap();
+        assert_eq!((gained, lost), (BPS as u128 * 3_000, BPS as u128 / 2 * 1_000));
+    }
 }
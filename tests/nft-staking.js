@@ -4,8 +4,9 @@ const serumCmn = require("@project-serum/common");
 const { TOKEN_PROGRAM_ID, Token } = require("@solana/spl-token");
 const TokenInstructions = require("@project-serum/serum").TokenInstructions;
 const utils = require("./utils");
-const { User, claimForUsers } = require("./user");
+const { User, claimForUsers, getMetadata } = require("./user");
 const fs = require('fs');
+const { keccak_256 } = require('js-sha3');
 
 let program = anchor.workspace.NftStaking;
 
@@ -78,25 +79,130 @@ describe('Multiuser NFT Staking', () => {
   });
 
   it("Create reward per token account", async () => {
-    await funder.createCandyMachineRewardPerToken();
+    const event = await nextEvent("CandyMachineRewardPerTokenCreated", () => funder.createCandyMachineRewardPerToken());
+    assert.ok(event.pool.equals(funder.poolPubkey));
+    assert.ok(event.authority.equals(funder.pubkey));
+  })
+
+  it("Create reward streams account", async () => {
+    const event = await nextEvent("RewardStreamsCreated", () => funder.createRewardStreams());
+    assert.ok(event.pool.equals(funder.poolPubkey));
+    assert.ok(event.authority.equals(funder.pubkey));
+  })
+
+  it("Create set bonus account", async () => {
+    const event = await nextEvent("SetBonusCreated", () => funder.createSetBonus());
+    assert.ok(event.pool.equals(funder.poolPubkey));
+    assert.ok(event.authority.equals(funder.pubkey));
+    assert.ok(event.timestamp.toNumber() > 0);
   })
 
   it("set reward per token account", async () => {
     await funder.setCandyMachineRewardPerToken(c1, 1);
+
+    const rewardObject = await program.account.candyMachineRewardPerToken.fetch(await cmRewardPerTokenPubkey(funder.poolPubkey));
+    const index = rewardObject.candyMachines.findIndex(c => c.equals(c1));
+    assert.equal(rewardObject.rewardPerTokens[index].toString(), anchor.web3.LAMPORTS_PER_SOL.toString());
+  })
+
+  it("set reward per token account before its eta or with other arguments", async () => {
+    const cmRewardPerToken = await cmRewardPerTokenPubkey(funder.poolPubkey);
+    const pendingAction = await funder.queueAction('setCandyMachineRewardPerToken', c2, new anchor.BN(anchor.web3.LAMPORTS_PER_SOL));
+    const actionObject = await program.account.pendingAction.fetch(pendingAction);
+    const accounts = {
+      pool: funder.poolPubkey,
+      vault: funder.admin.vaultPubkey,
+      cmRewardPerToken,
+      pendingAction,
+      proposer: actionObject.proposer,
+      authority: funder.pubkey,
+      systemProgram: anchor.web3.SystemProgram.programId,
+    };
+
+    let error;
+    try {
+      await funder.program.rpc.setCandyMachineRewardPerToken(c2, new anchor.BN(anchor.web3.LAMPORTS_PER_SOL), { accounts });
+    } catch(e) {
+      error = e;
+    }
+    assert.equal(error.msg, "Queued action can't run before its eta.");
+
+    await funder.waitForAction(pendingAction);
+    error = undefined;
+    try {
+      await funder.program.rpc.setCandyMachineRewardPerToken(c2, new anchor.BN(2 * anchor.web3.LAMPORTS_PER_SOL), { accounts });
+    } catch(e) {
+      error = e;
+    }
+    assert.equal(error.msg, "Arguments don't match the queued action.");
+
+    // Neither call changed the rate, and the action is still queued.
+    const rewardObject = await program.account.candyMachineRewardPerToken.fetch(cmRewardPerToken);
+    assert.equal(rewardObject.candyMachines.findIndex(c => c.equals(c2)), -1);
+    assert.ok(await provider.connection.getAccountInfo(pendingAction));
+    await funder.cancelPendingAction(pendingAction);
   })
 
   it("remove reward per token account", async () => {
     await funder.removeCandyMachineRewardPerToken(c1);
+
+    const rewardObject = await program.account.candyMachineRewardPerToken.fetch(await cmRewardPerTokenPubkey(funder.poolPubkey));
+    assert.equal(rewardObject.candyMachines.findIndex(c => c.equals(c1)), -1);
   })
 
   it("Add candy machine", async () => {
-    await funder.addCandyMachine(c1, 1, true, funder.admin.vaultPubkey);
-    await funder.addCandyMachine(c2, 2, false, funder.admin.vaultPubkey);
+    // c1 has no lock so its NFT can be unstaked right away.
+    await funder.addCandyMachine(c1, 0, funder.admin.vaultPubkey);
+    await funder.addCandyMachine(c2, 2, funder.admin.vaultPubkey);
   })
 
-  it("Set verify candy machine", async () => {
-    await funder.setVerifyCandyMachine(c1, false, funder.admin.vaultPubkey);
-    await funder.setVerifyCandyMachine(c2, true, funder.admin.vaultPubkey);
+  it("Remove candy machine or set rarity root before its eta", async () => {
+    const removeAction = await funder.queueAction('removeCandyMachine', c2);
+    const rootAction = await funder.queueAction('setRarityRoot', Array(32).fill(1));
+    const removeObject = await program.account.pendingAction.fetch(removeAction);
+    const rootObject = await program.account.pendingAction.fetch(rootAction);
+
+    let error;
+    try {
+      await funder.program.rpc.removeCandyMachine(c2, {
+        accounts: {
+          pool: funder.poolPubkey,
+          vault: funder.admin.vaultPubkey,
+          cmRewardPerToken: await cmRewardPerTokenPubkey(funder.poolPubkey),
+          pendingAction: removeAction,
+          proposer: removeObject.proposer,
+          authority: funder.pubkey,
+        },
+      });
+    } catch(e) {
+      error = e;
+    }
+    assert.equal(error.msg, "Queued action can't run before its eta.");
+
+    error = undefined;
+    try {
+      await funder.program.rpc.setRarityRoot(Array(32).fill(1), {
+        accounts: {
+          pool: funder.poolPubkey,
+          pendingAction: rootAction,
+          proposer: rootObject.proposer,
+          authority: funder.pubkey,
+        },
+      });
+    } catch(e) {
+      error = e;
+    }
+    assert.equal(error.msg, "Queued action can't run before its eta.");
+
+    // c2 still takes stakes and the rarity root is unset.
+    const vaultObject = await program.account.vault.fetch(funder.admin.vaultPubkey);
+    const index = vaultObject.candyMachines.findIndex(c => c.equals(c2));
+    assert.equal(vaultObject.retired[index], false);
+    const poolObject = await program.account.pool.fetch(funder.poolPubkey);
+    assert.deepEqual(poolObject.rarityRoot, Array(32).fill(0));
+
+    await funder.cancelPendingAction(removeAction);
+    await funder.cancelPendingAction(rootAction);
   })
 
   it("create nft user 1", async () => {
@@ -108,20 +214,101 @@ describe('Multiuser NFT Staking', () => {
     let user = users[0];
     await user.createUserStakingAccount(pool);
     await user.createUserStoreAccount(pool);
+    const emptySize = await getAccountSize(user.userStorePubkey);
     await user.stakeNFTToken(funder.admin);
 
     const vaultObject = await program.account.vault.fetch(funder.admin.vaultPubkey);
     const nfts = vaultObject.nfts;
+    // The store grows to fit the staked NFT.
+    const storeObject = await program.account.userStore.fetch(user.userStorePubkey);
+    assert.equal(storeObject.nftMints.length, 1);
+    assert.ok(await getAccountSize(user.userStorePubkey) > emptySize);
+    user.emptyStoreSize = emptySize;
   });
 
-  it('claim', async () => {
+  it('report solvency projects the liability of what is staked', async () => {
+    await wait(2);
+    const poolObject = await program.account.pool.fetch(funder.poolPubkey);
+    const report = await solvencyReport(funder);
+
+    // One NFT at 1x emits its candy machine's daily rate.
+    assert.equal(report.emissionPerDay.toString(), (await candyMachineRate(funder.poolPubkey, c1)).toString());
+    // The report adds what accrued since the last update to the recorded liability.
+    assert.ok(report.rewardLiability.gt(poolObject.rewardLiability.div(new anchor.BN(1_000_000_000_000))));
+    // Nothing has been deposited yet, so the vault has no runway.
+    assert.equal(report.rewardVaultBalance.toNumber(), 0);
+    assert.equal(report.runway.toNumber(), 0);
+  })
+
+  it('closing a store that still holds an NFT is rejected', async () => {
     let user = users[0];
+    let error;
+    try {
+      await user.closeUserStore();
+    } catch(e) {
+      error = e;
+    }
+    assert.equal(error.msg, "User store still has NFTs staked.");
+    const userObject = await program.account.user.fetch(user.userPubkey);
+    assert.equal(userObject.stores, 2);
+    assert.ok(await provider.connection.getAccountInfo(user.userStorePubkey));
+  })
+
+  it('claim from an empty reward vault', async () => {
+    let user = users[0];
+    await wait(2);
     await claimForUsers([user], funder.admin.vaultPubkey);
+
+    // Nothing is paid; what's owed stays pending.
+    assert.equal(await getTokenBalance(user.mintRewardsPubkey), 0);
+    const storeObject = await program.account.userStore.fetch(user.userStorePubkey);
+    assert.ok(storeObject.rewardTokenPending.toNumber() > 0);
+    user.rewardDebt = storeObject.rewardTokenPending.toNumber();
+  })
+
+  it('claim pays the carried debt once the vault is funded', async () => {
+    let user = users[0];
+    await funder.depositRewards();
+    await claimForUsers([user], funder.admin.vaultPubkey);
+
+    const storeObject = await program.account.userStore.fetch(user.userStorePubkey);
+    const poolObject = await program.account.pool.fetch(funder.poolPubkey);
+    const paid = (await provider.connection.getTokenAccountBalance(user.mintRewardsPubkey)).value.amount;
+    assert.ok(Number(paid) >= Math.min(user.rewardDebt, 10 * anchor.web3.LAMPORTS_PER_SOL));
+    // Either the debt is settled or the vault was drained paying it.
+    const vaultBalance = (await provider.connection.getTokenAccountBalance(poolObject.rewardVault)).value.amount;
+    assert.ok(storeObject.rewardTokenPending.toNumber() == 0 || vaultBalance == '0');
+  })
+
+  it('claim takes what it pays off the reward liability', async () => {
+    let user = users[0];
+    let poolObject = await program.account.pool.fetch(funder.poolPubkey);
+    let vaultObject = await program.account.vault.fetch(funder.admin.vaultPubkey);
+    const index = vaultObject.candyMachines.findIndex(c => c.equals(c1));
+    const [liability, acc] = [poolObject.rewardLiability, vaultObject.accRewardPerShares[index]];
+    const before = (await provider.connection.getTokenAccountBalance(user.mintRewardsPubkey)).value.amount;
+
+    await wait(2);
+    await user.claim(funder.admin.vaultPubkey);
+
+    poolObject = await program.account.pool.fetch(funder.poolPubkey);
+    vaultObject = await program.account.vault.fetch(funder.admin.vaultPubkey);
+    const after = (await provider.connection.getTokenAccountBalance(user.mintRewardsPubkey)).value.amount;
+    const paid = new anchor.BN(after).sub(new anchor.BN(before));
+    assert.ok(paid.gtn(0));
+    // The only staked NFT has weight 1x, so the liability grew by its accumulator and shrank by the payout.
+    const accrued = vaultObject.accRewardPerShares[index].sub(acc);
+    assert.ok(poolObject.rewardLiability.eq(liability.add(accrued).sub(paid.mul(new anchor.BN(1_000_000_000_000)))));
   })
 
   it('unstaking', async () => {
     let user = users[0];
     await user.unstakeNFTToken(funder.admin);
+
+    // The store shrinks back and refunds its extra rent.
+    const storeObject = await program.account.userStore.fetch(user.userStorePubkey);
+    assert.equal(storeObject.nftMints.length, 0);
+    assert.equal(await getAccountSize(user.userStorePubkey), user.emptyStoreSize);
   })
 
   it('claim', async () => {
@@ -129,9 +316,500 @@ describe('Multiuser NFT Staking', () => {
     await claimForUsers([user], funder.admin.vaultPubkey);
   })
 
+  it('claim reward streams through remaining accounts', async () => {
+    let user = users[0];
+    const streamMint = await utils.createMint(provider, 9);
+    const now = await getBlockTime();
+    const streamVault = await funder.addRewardStream(streamMint, 86_400 * anchor.web3.LAMPORTS_PER_SOL, now, now + 86_400);
+    await streamMint.mintTo(streamVault, envProvider.wallet.payer, [], 1_000 * anchor.web3.LAMPORTS_PER_SOL);
+    const streamAccount = await streamMint.createAccount(user.pubkey);
+
+    await user.createNFT();
+    await user.stakeNFTToken(funder.admin);
+    await wait(2);
+
+    // Without a pair for the stream, claiming leaves it pending.
+    await user.claim(funder.admin.vaultPubkey);
+    let storeObject = await program.account.userStore.fetch(user.userStorePubkey);
+    assert.ok(storeObject.streamRewardsPending[0].gtn(0));
+    assert.equal(await getTokenBalance(streamAccount), 0);
+
+    // A pair in the wrong order is rejected.
+    let error;
+    try {
+      await user.claim(funder.admin.vaultPubkey, [{ vault: streamAccount, account: streamVault }]);
+    } catch(e) {
+      error = e;
+    }
+    assert.equal(error.msg, "Remaining accounts don't form valid per-NFT groups.");
+
+    user.stream = { vault: streamVault, account: streamAccount };
+    await user.claim(funder.admin.vaultPubkey, [user.stream]);
+    assert.ok(await getTokenBalance(streamAccount) > 0);
+    storeObject = await program.account.userStore.fetch(user.userStorePubkey);
+    // Only the sub-token remainder, scaled by REWARD_PRECISION, stays pending.
+    assert.ok(storeObject.streamRewardsPending[0].lt(new anchor.BN(1_000_000_000_000)));
+
+    await user.unstakeNFTToken(funder.admin);
+  })
+
+  it('a pool without reward streams still stakes, claims and unstakes', async () => {
+    // Pools from before reward streams never created the account.
+    const oldFunder = new User(98);
+    await oldFunder.init(1_000_000_000, lpMintPubkey, 100_000_000_000_000, mintRewards.publicKey, 100_000_000_000);
+    await oldFunder.initializePool(anchor.web3.Keypair.generate());
+    await oldFunder.createCandyMachineRewardPerToken();
+    await oldFunder.createSetBonus();
+    await oldFunder.addCandyMachine(c1, 0, oldFunder.admin.vaultPubkey);
+    const [rewardStreams] = await anchor.web3.PublicKey.findProgramAddress(
+      [oldFunder.poolPubkey.toBuffer(), Buffer.from('reward_streams')],
+      program.programId
+    );
+    assert.equal(await provider.connection.getAccountInfo(rewardStreams), null);
+
+    const staker = new User(2);
+    await staker.init(1_000_000_000, lpMintPubkey, 0, mintRewards.publicKey, 0);
+    await staker.createUserStakingAccount(oldFunder.poolPubkey);
+    await staker.createUserStoreAccount(oldFunder.poolPubkey);
+    await staker.createNFT();
+    await staker.stakeNFTToken(oldFunder.admin);
+    await wait(1);
+    await staker.claim(oldFunder.admin.vaultPubkey);
+    await staker.unstakeNFTToken(oldFunder.admin);
+
+    const storeObject = await program.account.userStore.fetch(staker.userStorePubkey);
+    assert.equal(storeObject.nftMints.length, 0);
+    assert.equal(storeObject.streamRewardsPending.length, 0);
+    assert.equal(await provider.connection.getAccountInfo(rewardStreams), null);
+  })
+
+  it('stake and unstake a batch of NFTs', async () => {
+    let user = users[0];
+    let nfts = [];
+    for (let i = 0; i < 2; i++) {
+      await user.createNFT();
+      nfts.push({ nft: user.nft, nftMint: user.nftMint });
+    }
+
+    // A group whose metadata belongs to another mint is rejected before anything moves.
+    let error;
+    try {
+      await user.stakeMany(funder.admin, [
+        { ...nfts[0], metadata: await getMetadata(nfts[1].nftMint.publicKey) },
+        nfts[1],
+      ]);
+    } catch(e) {
+      error = e;
+    }
+    assert.equal(error.msg, "Remaining accounts don't form valid per-NFT groups.");
+    let storeObject = await program.account.userStore.fetch(user.userStorePubkey);
+    assert.equal(storeObject.nftMints.length, 0);
+
+    await user.stakeMany(funder.admin, nfts);
+    storeObject = await program.account.userStore.fetch(user.userStorePubkey);
+    assert.deepEqual(storeObject.nftMints.map(m => m.toBase58()), nfts.map(n => n.nftMint.publicKey.toBase58()));
+    for (const nft of nfts) {
+      assert.equal(await getTokenBalance(nft.nft.address), 0);
+      assert.equal(await getTokenBalance(nft.stakeToAccount), 1);
+    }
+
+    // Unstaking to a token account the owner doesn't hold is rejected.
+    const elsewhere = await nfts[0].nftMint.createAccount(funder.pubkey);
+    error = undefined;
+    try {
+      await user.unstakeMany(funder.admin, [
+        { ...nfts[0], nft: { address: elsewhere } },
+        nfts[1],
+      ]);
+    } catch(e) {
+      error = e;
+    }
+    assert.equal(error.msg, "Remaining accounts don't form valid per-NFT groups.");
+    storeObject = await program.account.userStore.fetch(user.userStorePubkey);
+    assert.equal(storeObject.nftMints.length, 2);
+
+    await user.unstakeMany(funder.admin, nfts);
+    storeObject = await program.account.userStore.fetch(user.userStorePubkey);
+    assert.equal(storeObject.nftMints.length, 0);
+    for (const nft of nfts) {
+      assert.equal(await getTokenBalance(nft.nft.address), 1);
+    }
+  })
+
+  it('unstaking before the lock ends is rejected', async () => {
+    let user = users[0];
+    // Lock c1 for a day; the lock is fixed when the NFT is staked.
+    await funder.addCandyMachine(c1, 1, funder.admin.vaultPubkey);
+    await user.createNFT();
+    await user.stakeNFTToken(funder.admin);
+
+    let error;
+    try {
+      await user.unstakeNFTToken(funder.admin);
+    } catch(e) {
+      error = e;
+    }
+    assert.equal(error.msg, "NFT is still locked.");
+    // The NFT is still staked and still held by the pool.
+    const storeObject = await program.account.userStore.fetch(user.userStorePubkey);
+    assert.equal(storeObject.nftMints.length, 1);
+    assert.ok(storeObject.nftMints[0].equals(user.nftMint.publicKey));
+    assert.equal(storeObject.types[0], 1);
+    assert.ok(storeObject.unlockTimes[0].toNumber() > storeObject.stakedTimes[0].toNumber());
+    assert.equal(await getTokenBalance(user.nft.address), 0);
+    user.pendingAtLockedStake = storeObject.rewardTokenPending.toNumber();
+  })
+
+  it('emergency unstaking before the lock ends is rejected', async () => {
+    let user = users[0];
+    let error;
+    try {
+      await user.unstakeNFTToken(funder.admin, true);
+    } catch(e) {
+      error = e;
+    }
+    assert.equal(error.msg, "NFT is still locked.");
+    const storeObject = await program.account.userStore.fetch(user.userStorePubkey);
+    assert.equal(storeObject.nftMints.length, 1);
+    assert.equal(storeObject.rewardTokenPending.toNumber(), user.pendingAtLockedStake);
+    assert.equal(await getTokenBalance(user.nft.address), 0);
+  })
+
+  it('early unstake of a soft-locked NFT forfeits its accrual', async () => {
+    let user = users[0];
+    // A full penalty forfeits everything the locked NFT earned.
+    await funder.setEarlyUnstakePenalty(c1, 10000);
+    await wait(2);
+
+    // Claim holds the penalty back while the NFT is locked.
+    await claimForUsers([user], funder.admin.vaultPubkey);
+    let storeObject = await program.account.userStore.fetch(user.userStorePubkey);
+    const withheld = storeObject.withheld.toNumber();
+    const pendingAtClaim = storeObject.rewardTokenPending.toNumber();
+    assert.ok(withheld > 0);
+    assert.ok(pendingAtClaim >= withheld);
+
+    await user.unstakeNFTToken(funder.admin);
+
+    // Everything the NFT earned is forfeited, what was held back at the claim and since.
+    storeObject = await program.account.userStore.fetch(user.userStorePubkey);
+    assert.equal(storeObject.nftMints.length, 0);
+    assert.ok(Math.abs(storeObject.rewardTokenPending.toNumber() - (pendingAtClaim - withheld)) <= 1);
+    assert.equal(storeObject.withheld.toNumber(), 0);
+    assert.equal(await getTokenBalance(user.nft.address), 1);
+  })
+
+  it('early unstake penalty above 100% is rejected', async () => {
+    let error;
+    try {
+      await funder.setEarlyUnstakePenalty(c1, 10001);
+    } catch(e) {
+      error = e;
+    }
+    assert.equal(error.msg, "Penalty can't exceed 10000 basis points.");
+    const vaultObject = await program.account.vault.fetch(funder.admin.vaultPubkey);
+    const index = vaultObject.candyMachines.findIndex(c => c.equals(c1));
+    assert.equal(vaultObject.earlyUnstakePenaltyBps[index], 10000);
+  })
+
+  it('emergency unstaking an unlocked NFT keeps the store\'s pending rewards', async () => {
+    let user = users[0];
+    // New c1 stakes are unlocked again.
+    await funder.addCandyMachine(c1, 0, funder.admin.vaultPubkey);
+    await user.createNFT();
+    await user.stakeNFTToken(funder.admin);
+    await wait(2);
+    let storeObject = await program.account.userStore.fetch(user.userStorePubkey);
+    const pending = storeObject.rewardTokenPending.toNumber();
+
+    await user.unstakeNFTToken(funder.admin, true);
+
+    // Only what the NFT earned since the store was last settled is forfeited.
+    storeObject = await program.account.userStore.fetch(user.userStorePubkey);
+    assert.equal(storeObject.nftMints.length, 0);
+    assert.equal(storeObject.rewardTokenPending.toNumber(), pending);
+    assert.equal(await getTokenBalance(user.nft.address), 1);
+  })
+
+  it('stake with a rarity tier proof', async () => {
+    let user = users[0];
+    await user.createNFT();
+    const leaf = rarityLeaf(user.nftMint.publicKey, 1);
+    const sibling = Buffer.from(keccak_256.arrayBuffer('other leaf'));
+    await funder.setRarityRoot(Array.from(hashPair(leaf, sibling)));
+
+    await user.stakeNFTToken(funder.admin, 1, [Array.from(sibling)]);
+    const storeObject = await program.account.userStore.fetch(user.userStorePubkey);
+    assert.equal(storeObject.nftMints.length, 1);
+    assert.equal(storeObject.tiers[0], 1);
+
+    await user.unstakeNFTToken(funder.admin);
+  })
+
+  it('stake with a wrong rarity tier proof', async () => {
+    let user = users[0];
+    await user.createNFT();
+    const sibling = Buffer.from(keccak_256.arrayBuffer('other leaf'));
+
+    let error;
+    try {
+      await user.stakeNFTToken(funder.admin, 1, [Array.from(sibling)]);
+    } catch(e) {
+      error = e;
+    }
+    assert.equal(error.msg, "Rarity tier proof doesn't match the pool's rarity root.");
+    const storeObject = await program.account.userStore.fetch(user.userStorePubkey);
+    assert.equal(storeObject.nftMints.length, 0);
+    assert.equal(await getTokenBalance(user.nft.address), 1);
+  })
+
+  it('soft staking freezes the NFT in the wallet', async () => {
+    let user = users[0];
+    await user.createNFT();
+    await user.softStakeNFTToken(funder.admin);
+
+    const storeObject = await program.account.userStore.fetch(user.userStorePubkey);
+    assert.equal(storeObject.nftMints.length, 1);
+    assert.equal(storeObject.softStaked[0], true);
+    const accountInfo = await user.nftMint.getAccountInfo(user.nft.address);
+    assert.equal(accountInfo.isFrozen, true);
+    assert.ok(accountInfo.delegate.equals(funder.admin.poolSigner));
+    assert.equal(await getTokenBalance(user.nft.address), 1);
+  })
+
+  it('soft staked NFT can\'t be moved', async () => {
+    let user = users[0];
+    const other = await user.nftMint.createAccount(funder.pubkey);
+    let error;
+    try {
+      await user.nftMint.transfer(user.nft.address, other, user.pubkey, [], 1);
+    } catch(e) {
+      error = e;
+    }
+    assert.ok(error);
+    assert.equal(await getTokenBalance(user.nft.address), 1);
+    assert.equal(await getTokenBalance(other), 0);
+  })
+
+  it('soft unstaking thaws the NFT', async () => {
+    let user = users[0];
+    await user.softStakeNFTToken(funder.admin, true);
+
+    const storeObject = await program.account.userStore.fetch(user.userStorePubkey);
+    assert.equal(storeObject.nftMints.length, 0);
+    const accountInfo = await user.nftMint.getAccountInfo(user.nft.address);
+    assert.equal(accountInfo.isFrozen, false);
+    assert.equal(accountInfo.delegate, null);
+    assert.equal(await getTokenBalance(user.nft.address), 1);
+  })
+
+  it('set bonus stays exact across an emission schedule change', async () => {
+    let user = users[0];
+    // A full bonus doubles what a complete set earns.
+    await funder.setSetBonus([c1], 10000);
+    await user.createNFT();
+    await user.stakeNFTToken(funder.admin);
+    let vaultObject = await program.account.vault.fetch(funder.admin.vaultPubkey);
+    const index = vaultObject.candyMachines.findIndex(c => c.equals(c1));
+    const accAtStake = vaultObject.accRewardPerShares[index];
+    let storeObject = await program.account.userStore.fetch(user.userStorePubkey);
+    const pendingAtStake = storeObject.rewardTokenPending.toNumber();
+
+    // Drop to a quarter of the rate halfway through: two 5 day steps at 50% have passed.
+    const now = await getBlockTime();
+    const day = 24 * 60 * 60;
+    await funder.setEmissionSchedule(new anchor.BN(now - 10 * day), new anchor.BN(now - 10 * day + 256 * 5 * day), new anchor.BN(5 * day), 5000);
+    await wait(2);
+    await user.unstakeNFTToken(funder.admin);
+
+    vaultObject = await program.account.vault.fetch(funder.admin.vaultPubkey);
+    storeObject = await program.account.userStore.fetch(user.userStorePubkey);
+    // The NFT has weight 1x, so it earned the accumulator's growth, and as much again as the bonus.
+    const expected = vaultObject.accRewardPerShares[index].sub(accAtStake).muln(2).div(new anchor.BN(1_000_000_000_000)).toNumber();
+    assert.ok(Math.abs(storeObject.rewardTokenPending.toNumber() - pendingAtStake - expected) <= 1);
+
+    // Back to a flat rate with no bonus.
+    await funder.setEmissionSchedule(new anchor.BN(0), new anchor.BN('18446744073709551615'), new anchor.BN(0), 10000);
+    await funder.setSetBonus([], 0);
+  })
+
+  it('emission steps down each interval and stops at the end', async () => {
+    let user = users[0];
+    // Halve the rate every 2 seconds for 6 steps.
+    const start = await getBlockTime();
+    const schedule = { start, end: start + 12, interval: 2, stepBps: 5000 };
+    await funder.setEmissionSchedule(new anchor.BN(schedule.start), new anchor.BN(schedule.end), new anchor.BN(schedule.interval), schedule.stepBps);
+
+    await user.createNFT();
+    await user.stakeNFTToken(funder.admin);
+    let vaultObject = await program.account.vault.fetch(funder.admin.vaultPubkey);
+    const index = vaultObject.candyMachines.findIndex(c => c.equals(c1));
+    const [from, accFrom] = [vaultObject.lastUpdateTime.toNumber(), vaultObject.accRewardPerShares[index]];
+    assert.ok(from < schedule.end);
+
+    // Past the end, accrual walks every remaining step at its own rate.
+    while (await getBlockTime() <= schedule.end + 1) {
+      await wait(1);
+    }
+    await claimForUsers([user], funder.admin.vaultPubkey);
+    vaultObject = await program.account.vault.fetch(funder.admin.vaultPubkey);
+    const to = vaultObject.lastUpdateTime.toNumber();
+    const rate = await candyMachineRate(funder.poolPubkey, c1);
+    const expected = rate.mul(new anchor.BN(emittedTime(schedule, from, to)))
+                         .mul(new anchor.BN(1_000_000_000_000))
+                         .div(new anchor.BN(24 * 60 * 60 * 10000));
+    assert.ok(vaultObject.accRewardPerShares[index].sub(accFrom).eq(expected));
+
+    // Nothing more accrues after the end.
+    const accAtEnd = vaultObject.accRewardPerShares[index];
+    await wait(2);
+    await user.unstakeNFTToken(funder.admin);
+    vaultObject = await program.account.vault.fetch(funder.admin.vaultPubkey);
+    assert.ok(vaultObject.accRewardPerShares[index].eq(accAtEnd));
+
+    await funder.setEmissionSchedule(new anchor.BN(0), new anchor.BN('18446744073709551615'), new anchor.BN(0), 10000);
+  })
+
+  it('emission schedule with more than 256 steps is rejected', async () => {
+    const start = await getBlockTime();
+    let error;
+    try {
+      await funder.setEmissionSchedule(new anchor.BN(start), new anchor.BN(start + 257 * 60), new anchor.BN(60), 9000);
+    } catch(e) {
+      error = e;
+    }
+    assert.equal(error.msg, "Emission schedule must end after it starts and only step down.");
+    let poolObject = await program.account.pool.fetch(funder.poolPubkey);
+    assert.equal(poolObject.emissionStepInterval.toNumber(), 0);
+    // The rejected change stays queued until cancelled.
+    const [rejectedAction] = await anchor.web3.PublicKey.findProgramAddress(
+      [funder.poolPubkey.toBuffer(), Buffer.from('pending_action'), poolObject.nextActionId.subn(1).toArrayLike(Buffer, 'le', 8)],
+      program.programId
+    );
+    await funder.cancelPendingAction(rejectedAction);
+
+    // 256 steps is still allowed.
+    await funder.setEmissionSchedule(new anchor.BN(start), new anchor.BN(start + 256 * 60), new anchor.BN(60), 9000);
+    poolObject = await program.account.pool.fetch(funder.poolPubkey);
+    assert.equal(poolObject.emissionStepInterval.toNumber(), 60);
+
+    await funder.setEmissionSchedule(new anchor.BN(0), new anchor.BN('18446744073709551615'), new anchor.BN(0), 10000);
+  })
+
+  it('pause staking only', async () => {
+    let user = users[0];
+    await funder.pausePool(null, 1);
+    let poolObject = await program.account.pool.fetch(funder.poolPubkey);
+    assert.equal(poolObject.pauseFlags, 1);
+
+    await user.createNFT();
+    let error;
+    try {
+      await user.stakeNFTToken(funder.admin);
+    } catch(e) {
+      error = e;
+    }
+    assert.equal(error.msg, "Staking is paused.");
+    const storeObject = await program.account.userStore.fetch(user.userStorePubkey);
+    assert.equal(storeObject.nftMints.length, 0);
+    assert.equal(await getTokenBalance(user.nft.address), 1);
+
+    // Claims keep working while staking is paused.
+    await claimForUsers([user], funder.admin.vaultPubkey);
+
+    await funder.unpausePool(null, 1);
+    poolObject = await program.account.pool.fetch(funder.poolPubkey);
+    assert.equal(poolObject.pauseFlags, 0);
+  })
+
+  it('pause with unknown flags or without the pauser role', async () => {
+    let error;
+    try {
+      await funder.pausePool(null, 8);
+    } catch(e) {
+      error = e;
+    }
+    assert.equal(error.msg, "Unknown pause flags.");
+
+    error = undefined;
+    try {
+      await users[0].pausePool(null, 1);
+    } catch(e) {
+      error = e;
+    }
+    assert.equal(error.msg, "Signer doesn't hold the role this instruction needs.");
+    const poolObject = await program.account.pool.fetch(funder.poolPubkey);
+    assert.equal(poolObject.pauseFlags, 0);
+  })
+
+  it('rate change splits accrual and a removed candy machine keeps its accumulator', async () => {
+    let user = users[0];
+    const perSecond = (rate, seconds) => rate.muln(seconds).mul(new anchor.BN(1_000_000_000_000)).divn(24 * 60 * 60);
+    await user.createNFT();
+    await user.stakeNFTToken(funder.admin);
+    let vaultObject = await program.account.vault.fetch(funder.admin.vaultPubkey);
+    const index = vaultObject.candyMachines.findIndex(c => c.equals(c1));
+    const [t0, acc0] = [vaultObject.lastUpdateTime.toNumber(), vaultObject.accRewardPerShares[index]];
+    const oldRate = await candyMachineRate(funder.poolPubkey, c1);
+    let storeObject = await program.account.userStore.fetch(user.userStorePubkey);
+    const pending0 = storeObject.rewardTokenPending;
+
+    // The change settles everything before it at the old rate.
+    await funder.setCandyMachineRewardPerToken(c1, 3);
+    vaultObject = await program.account.vault.fetch(funder.admin.vaultPubkey);
+    const [t1, acc1] = [vaultObject.lastUpdateTime.toNumber(), vaultObject.accRewardPerShares[index]];
+    assert.ok(acc1.sub(acc0).eq(perSecond(oldRate, t1 - t0)));
+
+    // And everything after it at the new one.
+    const newRate = await candyMachineRate(funder.poolPubkey, c1);
+    assert.equal(newRate.toString(), (3 * anchor.web3.LAMPORTS_PER_SOL).toString());
+    await wait(2);
+    await funder.removeCandyMachine(c1, funder.admin.vaultPubkey);
+    vaultObject = await program.account.vault.fetch(funder.admin.vaultPubkey);
+    const [t2, acc2] = [vaultObject.lastUpdateTime.toNumber(), vaultObject.accRewardPerShares[index]];
+    assert.ok(acc2.sub(acc1).eq(perSecond(newRate, t2 - t1)));
+
+    // The removed candy machine stays while its NFT is staked, with its accumulator frozen.
+    assert.ok(vaultObject.candyMachines[index].equals(c1));
+    assert.equal(vaultObject.retired[index], true);
+    await wait(2);
+    await user.unstakeNFTToken(funder.admin);
+    vaultObject = await program.account.vault.fetch(funder.admin.vaultPubkey);
+    const stillListed = vaultObject.candyMachines.findIndex(c => c.equals(c1));
+    assert.ok(stillListed == -1 || vaultObject.accRewardPerShares[stillListed].eq(acc2));
+
+    // The NFT earned both parts at weight 1x, and nothing after the removal.
+    storeObject = await program.account.userStore.fetch(user.userStorePubkey);
+    const expected = acc2.sub(acc0).div(new anchor.BN(1_000_000_000_000));
+    assert.ok(storeObject.rewardTokenPending.sub(pending0).sub(expected).abs().lten(1));
+  })
+
   it("Remove candy machine", async () => {
     await funder.removeCandyMachine(c1, funder.admin.vaultPubkey);
     await funder.removeCandyMachine(c2, funder.admin.vaultPubkey);
+
+    // Neither takes new stakes once the timelock has passed.
+    const vaultObject = await program.account.vault.fetch(funder.admin.vaultPubkey);
+    for (const c of [c1, c2]) {
+      const index = vaultObject.candyMachines.findIndex(x => x.equals(c));
+      assert.ok(index == -1 || vaultObject.retired[index]);
+    }
+  })
+
+  it('failed stake leaves the store size unchanged', async () => {
+    let user = users[0];
+    const size = await getAccountSize(user.userStorePubkey);
+    const lamports = (await provider.connection.getAccountInfo(user.userStorePubkey)).lamports;
+
+    let error;
+    try {
+      await user.stakeNFTToken(funder.admin);
+    } catch(e) {
+      error = e;
+    }
+    assert.equal(error.msg, "Candy machine not found.");
+    assert.equal(await getAccountSize(user.userStorePubkey), size);
+    assert.equal((await provider.connection.getAccountInfo(user.userStorePubkey)).lamports, lamports);
   })
 
   it('deposit staking', async () => {
@@ -155,7 +833,33 @@ describe('Multiuser NFT Staking', () => {
   })
 
   it('withdraw rewards', async () => {
-    await funder.withdrawRewards();
+    const pendingAction = await funder.withdrawRewards();
+    await funder.cancelPendingAction(pendingAction);
+  })
+
+  it('execute withdraw rewards before the timelock delay', async () => {
+    const pendingAction = await funder.withdrawRewards();
+    let error;
+    try {
+      await funder.executeWithdrawReward(pendingAction);
+    } catch(e) {
+      error = e;
+    }
+    assert.equal(error.msg, "Queued action can't run before its eta.");
+    // The action is still queued.
+    const actionObject = await program.account.pendingAction.fetch(pendingAction);
+    assert.equal(actionObject.value.toString(), (10 * anchor.web3.LAMPORTS_PER_SOL).toString());
+    await funder.cancelPendingAction(pendingAction);
+  })
+
+  it('execute withdraw rewards after the timelock delay', async () => {
+    const before = await getTokenBalance(funder.mintRewardsPubkey);
+    const pendingAction = await funder.withdrawRewards();
+    await funder.waitForAction(pendingAction);
+    await funder.executeWithdrawReward(pendingAction);
+
+    assert.equal(await getTokenBalance(funder.mintRewardsPubkey) - before, 10);
+    assert.equal(await provider.connection.getAccountInfo(pendingAction), null);
   })
 
   it('try withdraw rewards by user', async () => {
@@ -166,24 +870,193 @@ describe('Multiuser NFT Staking', () => {
     }
   })
 
-  it('close user', async () => {
+  it('only the treasurer can withdraw rewards', async () => {
+    const treasurer = new User(97);
+    const pauser = new User(96);
+    for (const u of [treasurer, pauser]) {
+      await u.init(1_000_000_000, lpMintPubkey, 0, mintRewards.publicKey, 0);
+      u.poolPubkey = funder.poolPubkey;
+    }
+    await funder.setRoles(funder.pubkey, funder.pubkey, pauser.pubkey, treasurer.pubkey);
+
+    // Neither the pauser nor the rate manager, who is also the authority, can queue a withdrawal.
+    for (const u of [pauser, funder]) {
+      let error;
+      try {
+        await u.withdrawRewards();
+      } catch(e) {
+        error = e;
+      }
+      assert.equal(error.msg, "Signer doesn't hold the role this instruction needs.");
+    }
+
+    const pendingAction = await treasurer.withdrawRewards();
+    await treasurer.waitForAction(pendingAction);
+    // Anyone can execute the queued withdrawal; it pays the treasurer's account.
+    await funder.executeWithdrawReward(pendingAction);
+    assert.equal(await getTokenBalance(treasurer.mintRewardsPubkey), 10);
+
+    // The treasurer keeps its role for the authority handover below.
+    await funder.setRoles(funder.pubkey, funder.pubkey, funder.pubkey, treasurer.pubkey);
+    users.treasurer = treasurer;
+  })
+
+  it('close the user stores, then the user', async () => {
     let user = users[0];
+    const [firstStore] = await anchor.web3.PublicKey.findProgramAddress(
+      [user.pubkey.toBuffer(), funder.poolPubkey.toBuffer(), Buffer.from("user"), [1]],
+      program.programId
+    );
+
+    // The user can't close while it has open stores.
+    let error;
+    try {
+      await user.closeUser();
+    } catch(e) {
+      error = e;
+    }
+    assert.ok(error);
+
+    // Empty stores close once everything they earned is claimed.
+    await user.claim(funder.admin.vaultPubkey, [user.stream]);
+    const storeObject = await program.account.userStore.fetch(user.userStorePubkey);
+    assert.equal(storeObject.nftMints.length, 0);
+    await user.closeUserStore();
+    assert.equal(await provider.connection.getAccountInfo(user.userStorePubkey), null);
+    let userObject = await program.account.user.fetch(user.userPubkey);
+    assert.equal(userObject.stores, 1);
+
+    await user.closeUserStore(firstStore);
+    assert.equal(await provider.connection.getAccountInfo(firstStore), null);
+    userObject = await program.account.user.fetch(user.userPubkey);
+    assert.equal(userObject.stores, 0);
+
     await user.closeUser();
+    assert.equal(await provider.connection.getAccountInfo(user.userPubkey), null);
   })
 
   it('pausePool', async () => {
     await funder.pausePool(null);
   })
+
+  it('accept authority by someone other than the pending authority', async () => {
+    await funder.proposeAuthority(users[0].pubkey);
+    let error;
+    try {
+      await funder.acceptAuthority();
+    } catch(e) {
+      error = e;
+    }
+    assert.equal(error.msg, "Signer is not the pending pool authority.");
+    // The proposal stands and the pool keeps its authority.
+    const poolObject = await program.account.pool.fetch(funder.poolPubkey);
+    assert.ok(poolObject.authority.equals(funder.pubkey));
+    assert.ok(poolObject.pendingAuthority.equals(users[0].pubkey));
+  })
+
+  it('accept authority by the pending authority', async () => {
+    await users[0].acceptAuthority();
+
+    const poolObject = await program.account.pool.fetch(funder.poolPubkey);
+    assert.ok(poolObject.authority.equals(users[0].pubkey));
+    assert.ok(poolObject.pendingAuthority.equals(anchor.web3.PublicKey.default));
+    // The roles the old authority held move with it; the others stay.
+    assert.ok(poolObject.rateManager.equals(users[0].pubkey));
+    assert.ok(poolObject.collectionManager.equals(users[0].pubkey));
+    assert.ok(poolObject.pauser.equals(users[0].pubkey));
+    assert.ok(poolObject.treasurer.equals(users.treasurer.pubkey));
+  })
 });  
 
 async function getTokenBalance(pubkey) {
   return parseFloat((await provider.connection.getTokenAccountBalance(pubkey)).value.uiAmount.toFixed(6))
 }
 
+async function cmRewardPerTokenPubkey(pool) {
+  return (await anchor.web3.PublicKey.findProgramAddress(
+    [pool.toBuffer(), Buffer.from('reward_per_token')],
+    program.programId
+  ))[0];
+}
+
+// keccak256(mint || tier), the leaf of a rarity Merkle tree.
+// Base daily rate of `candyMachine`: its own rate if it has one, else the pool's.
+async function candyMachineRate(pool, candyMachine) {
+  const rewardObject = await program.account.candyMachineRewardPerToken.fetch(await cmRewardPerTokenPubkey(pool));
+  const index = rewardObject.candyMachines.findIndex(c => c.equals(candyMachine));
+  if (index >= 0) {
+    return rewardObject.rewardPerTokens[index];
+  }
+  return (await program.account.pool.fetch(pool)).rewardPerToken;
+}
+
+// Seconds between `from` and `to` weighted by a stepped emission schedule, in basis points.
+function emittedTime(schedule, from, to) {
+  let total = 0;
+  let multiplier = 10000;
+  for (let stepStart = schedule.start; stepStart < schedule.end; stepStart += schedule.interval) {
+    const stepEnd = Math.min(stepStart + schedule.interval, schedule.end);
+    const [a, b] = [Math.max(from, stepStart), Math.min(to, stepEnd)];
+    if (b > a) {
+      total += (b - a) * multiplier;
+    }
+    multiplier = Math.floor(multiplier * schedule.stepBps / 10000);
+  }
+  return total;
+}
+
+// The pool's SolvencyReport event, read by simulating `report_solvency`.
+async function solvencyReport(funder) {
+  const poolObject = await program.account.pool.fetch(funder.poolPubkey);
+  const result = await program.simulate.reportSolvency({
+    accounts: {
+      pool: funder.poolPubkey,
+      vault: funder.admin.vaultPubkey,
+      cmRewardPerToken: await cmRewardPerTokenPubkey(funder.poolPubkey),
+      rewardVault: poolObject.rewardVault,
+    },
+  });
+  return result.events.find(e => e.name == 'SolvencyReport').data;
+}
+
+function rarityLeaf(mint, tier) {
+  return Buffer.from(keccak_256.arrayBuffer(Buffer.concat([mint.toBuffer(), Buffer.from([tier])])));
+}
+
+// Hashes a pair of Merkle nodes in sorted order, as the program does.
+function hashPair(a, b) {
+  const pair = Buffer.compare(a, b) <= 0 ? [a, b] : [b, a];
+  return Buffer.from(keccak_256.arrayBuffer(Buffer.concat(pair)));
+}
+
+async function getBlockTime() {
+  return await provider.connection.getBlockTime(await provider.connection.getSlot());
+}
+
+async function getAccountSize(pubkey) {
+  return (await provider.connection.getAccountInfo(pubkey)).data.length;
+}
+
 async function wait(seconds) {
   while(seconds > 0) {
     console.log("countdown " + seconds--);
     await new Promise(a=>setTimeout(a, 1000));
   }
   console.log("wait over");
-}
\ No newline at end of file
+}
+
+// Runs `fn` and returns the first `name` event the program emits meanwhile.
+async function nextEvent(name, fn) {
+  let listener;
+  const event = new Promise(resolve => {
+    listener = program.addEventListener(name, (event) => resolve(event));
+  });
+  try {
+    await fn();
+    const emitted = await Promise.race([event, wait(5)]);
+    assert.ok(emitted, `${name} was not emitted`);
+    return emitted;
+  } finally {
+    await program.removeEventListener(listener);
+  }
+}
@@ -15,6 +15,23 @@ async function claimForUsers(users, vault) {
         });
 }
 
+// Timelock delay the test pool runs with, in seconds.
+const TIMELOCK_DELAY = 2;
+
+// Kinds of the rate changes `queueAction` gates, keyed by the instruction they run.
+const ACTION_KINDS = {
+    setEmissionSchedule: 3,
+    setCandyMachineRewardPerToken: 4,
+    removeCandyMachineRewardPerToken: 5,
+    setRewardStream: 6,
+    setLoyaltyMultipliers: 7,
+    setTierMultipliers: 8,
+    setSetBonus: 9,
+    setEarlyUnstakePenalty: 10,
+    removeCandyMachine: 11,
+    setRarityRoot: 12,
+};
+
 const getMetadata = async (mint) => {
     const TOKEN_METADATA_PROGRAM_ID = new anchor.web3.PublicKey(
         'metaqbxxUerdq28cj1RbAWkYQm3ybzjb6a8bt518x1s',
@@ -31,6 +48,23 @@ const getMetadata = async (mint) => {
     )[0];
 };
 
+const getEdition = async (mint) => {
+    const TOKEN_METADATA_PROGRAM_ID = new anchor.web3.PublicKey(
+        'metaqbxxUerdq28cj1RbAWkYQm3ybzjb6a8bt518x1s',
+    );
+    return (
+        await anchor.web3.PublicKey.findProgramAddress(
+            [
+                Buffer.from('metadata'),
+                TOKEN_METADATA_PROGRAM_ID.toBuffer(),
+                mint.toBuffer(),
+                Buffer.from('edition'),
+            ],
+            TOKEN_METADATA_PROGRAM_ID,
+        )
+    )[0];
+};
+
 ///user can be an admin or a staker. either way, call init - then can call other methods
 class User {
     constructor(a) { this.id = a; }
@@ -109,6 +143,7 @@ class User {
         await this.program.rpc.initializePool(
             poolNonce,
             vaultNonce,
+            new anchor.BN(TIMELOCK_DELAY),
             {
                 accounts: {
                     authority: this.provider.wallet.publicKey,
@@ -192,7 +227,7 @@ class User {
         });
     }
 
-    async stakeNFTToken(admin) {
+    async stakeNFTToken(admin, tier = 0, proof = []) {
         var toWallet = anchor.web3.Keypair.generate();
 
         const [
@@ -222,7 +257,28 @@ class User {
             this.program.programId
         );
 
-        await this.program.rpc.stake({
+        const [
+            rewardStreams,
+        ] = await anchor.web3.PublicKey.findProgramAddress(
+            [
+                this.poolPubkey.toBuffer(),
+                Buffer.from('reward_streams')
+            ],
+            this.program.programId
+        );
+
+        const [
+            setBonus,
+        ] = await anchor.web3.PublicKey.findProgramAddress(
+            [
+                this.poolPubkey.toBuffer(),
+                Buffer.from('set_bonus')
+            ],
+            this.program.programId
+        );
+
+        // Tier 0 is the default rarity and needs no proof.
+        await this.program.rpc.stake(tier, proof, {
                 accounts: {
                     // Stake instance.
                     pool: this.poolPubkey,
@@ -231,21 +287,166 @@ class User {
                     lpTokenPoolVault: admin.lpTokenPoolVault,
                     lpTokenReceiver: this.lpTokenPubkey,
                     cmRewardPerToken,
+                    rewardStreams,
+                    setBonus,
                     // User.
                     user: this.userPubkey,
                     userStore: this.userStorePubkey,
                     owner: this.provider.wallet.publicKey,
                     stakeFromAccount: this.nft.address,
+                    nftMint: this.nftMint.publicKey,
                     // Program signers.
                     poolSigner,
                     tokenProgram: TOKEN_PROGRAM_ID,
+                    systemProgram: anchor.web3.SystemProgram.programId,
                     metadataInfo: metadata
                 },
             }
         );
     }
 
-    async pausePool(authority) {
+    // Accounts `stake_many`/`unstake_many` share; the per-NFT groups go in remaining accounts.
+    async stakeManyAccounts(admin) {
+        const [poolSigner] = await anchor.web3.PublicKey.findProgramAddress(
+            [this.poolPubkey.toBuffer()],
+            this.program.programId
+        );
+        const seeded = async (seed) => (await anchor.web3.PublicKey.findProgramAddress(
+            [this.poolPubkey.toBuffer(), Buffer.from(seed)],
+            this.program.programId
+        ))[0];
+
+        return {
+            pool: this.poolPubkey,
+            vault: admin.vaultPubkey,
+            user: this.userPubkey,
+            userStore: this.userStorePubkey,
+            cmRewardPerToken: await seeded('reward_per_token'),
+            rewardStreams: await seeded('reward_streams'),
+            setBonus: await seeded('set_bonus'),
+            owner: this.provider.wallet.publicKey,
+            poolSigner,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            systemProgram: anchor.web3.SystemProgram.programId,
+        };
+    }
+
+    // One (stake_from_account, stake_to_account, nft_mint, metadata_info) group per NFT.
+    async stakeAccountGroups(nfts) {
+        let remainingAccounts = [];
+        for (const nft of nfts) {
+            remainingAccounts.push(
+                { pubkey: nft.nft.address, isWritable: true, isSigner: false },
+                { pubkey: nft.stakeToAccount, isWritable: true, isSigner: false },
+                { pubkey: nft.nftMint.publicKey, isWritable: false, isSigner: false },
+                { pubkey: nft.metadata || await getMetadata(nft.nftMint.publicKey), isWritable: false, isSigner: false },
+            );
+        }
+        return remainingAccounts;
+    }
+
+    // Stakes each `{ nft, nftMint }` of `nfts` in one transaction, at tier 0.
+    async stakeMany(admin, nfts) {
+        let accounts = await this.stakeManyAccounts(admin);
+        for (const nft of nfts) {
+            if (!nft.stakeToAccount) {
+                nft.stakeToAccount = await nft.nftMint.createAccount(accounts.poolSigner);
+            }
+        }
+
+        await this.program.rpc.stakeMany(
+            nfts.map(() => 0),
+            nfts.map(() => []),
+            {
+                accounts,
+                remainingAccounts: await this.stakeAccountGroups(nfts),
+            }
+        );
+    }
+
+    // Unstakes each `{ nft, nftMint, stakeToAccount }` of `nfts` in one transaction.
+    async unstakeMany(admin, nfts) {
+        await this.program.rpc.unstakeMany({
+            accounts: await this.stakeManyAccounts(admin),
+            remainingAccounts: await this.stakeAccountGroups(nfts),
+        });
+    }
+
+    // Freezes the NFT in the owner's wallet, or thaws it back with `unstake`.
+    async softStakeNFTToken(admin, unstake = false) {
+        const [
+            _poolSigner,
+            _nonce,
+        ] = await anchor.web3.PublicKey.findProgramAddress(
+            [this.poolPubkey.toBuffer()],
+            this.program.programId
+        );
+        let poolSigner = _poolSigner;
+
+        let metadata = await getMetadata(this.nftMint.publicKey);
+        let edition = await getEdition(this.nftMint.publicKey);
+
+        const [
+            cmRewardPerToken,
+        ] = await anchor.web3.PublicKey.findProgramAddress(
+            [
+                this.poolPubkey.toBuffer(),
+                Buffer.from('reward_per_token')
+            ],
+            this.program.programId
+        );
+
+        const [
+            rewardStreams,
+        ] = await anchor.web3.PublicKey.findProgramAddress(
+            [
+                this.poolPubkey.toBuffer(),
+                Buffer.from('reward_streams')
+            ],
+            this.program.programId
+        );
+
+        const [
+            setBonus,
+        ] = await anchor.web3.PublicKey.findProgramAddress(
+            [
+                this.poolPubkey.toBuffer(),
+                Buffer.from('set_bonus')
+            ],
+            this.program.programId
+        );
+
+        const accounts = {
+            // Stake instance.
+            pool: this.poolPubkey,
+            vault: admin.vaultPubkey,
+            cmRewardPerToken,
+            rewardStreams,
+            setBonus,
+            // User.
+            user: this.userPubkey,
+            userStore: this.userStorePubkey,
+            owner: this.provider.wallet.publicKey,
+            stakeFromAccount: this.nft.address,
+            nftMint: this.nftMint.publicKey,
+            metadataInfo: metadata,
+            edition,
+            // Program signers.
+            poolSigner,
+            // Misc.
+            tokenProgram: TOKEN_PROGRAM_ID,
+            systemProgram: anchor.web3.SystemProgram.programId,
+            tokenMetadataProgram: new anchor.web3.PublicKey('metaqbxxUerdq28cj1RbAWkYQm3ybzjb6a8bt518x1s'),
+        };
+        if (unstake) {
+            await this.program.rpc.softUnstake({ accounts });
+        } else {
+            // Tier 0 is the default rarity and needs no proof.
+            await this.program.rpc.softStake(0, [], { accounts });
+        }
+    }
+
+    async pausePool(authority, flags = 7) {
         let poolObject = await this.program.account.pool.fetch(this.poolPubkey);
 
         const [
@@ -257,7 +458,7 @@ class User {
         );
         let poolSigner = _poolSigner;
 
-        await this.program.rpc.pause(
+        await this.program.rpc.pause(flags,
             {
                 accounts: {
                     lpTokenPoolVault: poolObject.lpTokenPoolVault,
@@ -297,30 +498,96 @@ class User {
         );
     }
 
-    async withdrawRewards() {
+    async queuePendingAction(method, ...args) {
         let poolObject = await this.program.account.pool.fetch(this.poolPubkey);
-
         const [
-            _poolSigner,
-            _nonce,
+            pendingAction,
+            nonce,
         ] = await anchor.web3.PublicKey.findProgramAddress(
-            [this.poolPubkey.toBuffer()],
+            [
+                this.poolPubkey.toBuffer(),
+                Buffer.from('pending_action'),
+                poolObject.nextActionId.toArrayLike(Buffer, 'le', 8)
+            ],
             this.program.programId
         );
-        let poolSigner = _poolSigner;
 
-        await this.program.rpc.withdrawReward(new anchor.BN(10 * anchor.web3.LAMPORTS_PER_SOL),
-            {
-                accounts: {
-                    rewardVault: poolObject.rewardVault,
-                    rewardAccount: this.mintRewardsPubkey,
-                    pool: this.poolPubkey,
-                    owner: this.provider.wallet.publicKey,
-                    poolSigner: poolSigner,
-                    tokenProgram: TOKEN_PROGRAM_ID,
-                },
+        await this.program.rpc[method](nonce, ...args, {
+            accounts: {
+                pool: this.poolPubkey,
+                pendingAction,
+                authority: this.provider.wallet.publicKey,
+                systemProgram: anchor.web3.SystemProgram.programId,
+            },
+        });
+        return pendingAction;
+    }
+
+    // Queues `method` with `args` as a rate change; returns the pending action.
+    async queueAction(method, ...args) {
+        const ix = this.program.idl.instructions.find(x => x.name == method);
+        const data = {};
+        ix.args.forEach((arg, i) => data[arg.name] = args[i]);
+        const encoded = this.program.coder.instruction.encode(method, data).slice(8);
+        return await this.queuePendingAction('queueAction', ACTION_KINDS[method], encoded);
+    }
+
+    // Waits until the cluster clock is past the action's eta.
+    async waitForAction(pendingAction) {
+        const actionObject = await this.program.account.pendingAction.fetch(pendingAction);
+        while (true) {
+            const slot = await this.provider.connection.getSlot();
+            if (await this.provider.connection.getBlockTime(slot) > actionObject.eta.toNumber()) {
+                return;
             }
-        );
+            await new Promise(a => setTimeout(a, 500));
+        }
+    }
+
+    // Queues a rate change, waits out the timelock delay and applies it.
+    async runTimelocked(method, args, accounts) {
+        const pendingAction = await this.queueAction(method, ...args);
+        await this.waitForAction(pendingAction);
+        const actionObject = await this.program.account.pendingAction.fetch(pendingAction);
+        await this.program.rpc[method](...args, {
+            accounts: {
+                ...accounts,
+                pendingAction,
+                proposer: actionObject.proposer,
+            },
+        });
+    }
+
+    async executeWithdrawReward(pendingAction) {
+        let poolObject = await this.program.account.pool.fetch(this.poolPubkey);
+        const actionObject = await this.program.account.pendingAction.fetch(pendingAction);
+        await this.program.rpc.executeWithdrawReward({
+            accounts: {
+                rewardVault: poolObject.rewardVault,
+                rewardAccount: actionObject.rewardAccount,
+                pool: this.poolPubkey,
+                pendingAction,
+                proposer: actionObject.proposer,
+                poolSigner: this.admin.poolSigner,
+                tokenProgram: TOKEN_PROGRAM_ID,
+            },
+        });
+    }
+
+    async withdrawRewards() {
+        return await this.queuePendingAction('queueWithdrawReward', new anchor.BN(10 * anchor.web3.LAMPORTS_PER_SOL), this.mintRewardsPubkey);
+    }
+
+    async cancelPendingAction(pendingAction) {
+        const actionObject = await this.program.account.pendingAction.fetch(pendingAction);
+        await this.program.rpc.cancelPendingAction({
+            accounts: {
+                pool: this.poolPubkey,
+                pendingAction,
+                proposer: actionObject.proposer,
+                authority: this.provider.wallet.publicKey,
+            },
+        });
     }
 
     async depositStake() {
@@ -377,7 +644,7 @@ class User {
         );
     }
 
-    async unpausePool(authority) {
+    async unpausePool(authority, flags = 7) {
         let poolObject = await this.program.account.pool.fetch(this.poolPubkey);
 
         const [
@@ -392,7 +659,7 @@ class User {
         let lpTokenPoolVault = await this.lpTokenMintObject.createAccount(poolSigner);
         this.admin.lpTokenPoolVault = lpTokenPoolVault;
 
-        await this.program.rpc.unpause(
+        await this.program.rpc.unpause(flags,
             {
                 accounts: {
                     lpTokenPoolVault: lpTokenPoolVault,
@@ -407,7 +674,7 @@ class User {
         );
     }
 
-    async unstakeNFTToken(admin) {
+    async unstakeNFTToken(admin, emergency = false) {
         let nftToken = new Token(this.provider.connection, this.nftMint.publicKey, TOKEN_PROGRAM_ID, this.provider.wallet.payer);
         const userNftAccount = await nftToken.getAccountInfo(this.nft.address);
         let mint = this.nft.mint;
@@ -440,7 +707,29 @@ class User {
             this.program.programId
         );
 
-        await this.program.rpc.unstake(
+        const [
+            rewardStreams,
+        ] = await anchor.web3.PublicKey.findProgramAddress(
+            [
+                this.poolPubkey.toBuffer(),
+                Buffer.from('reward_streams')
+            ],
+            this.program.programId
+        );
+
+        const [
+            setBonus,
+        ] = await anchor.web3.PublicKey.findProgramAddress(
+            [
+                this.poolPubkey.toBuffer(),
+                Buffer.from('set_bonus')
+            ],
+            this.program.programId
+        );
+
+        let metadata = await getMetadata(this.nftMint.publicKey);
+
+        await this.program.rpc[emergency ? 'emergencyUnstake' : 'unstake'](
             {
                 accounts: {
                     // Stake instance.
@@ -450,16 +739,21 @@ class User {
                     lpTokenPoolVault: admin.lpTokenPoolVault,
                     lpTokenReceiver: this.lpTokenPubkey,
                     cmRewardPerToken,
+                    rewardStreams,
+                    setBonus,
                     // User.
                     user: this.userPubkey,
                     userStore: this.userStorePubkey,
                     owner: this.provider.wallet.publicKey,
                     stakeFromAccount: this.nft.address,
+                    nftMint: this.nftMint.publicKey,
                     // Program signers.
                     poolSigner,
                     // Misc.
                     clock: anchor.web3.SYSVAR_CLOCK_PUBKEY,
                     tokenProgram: TOKEN_PROGRAM_ID,
+                    systemProgram: anchor.web3.SystemProgram.programId,
+                    metadataInfo: metadata
                 },
             });
     }
@@ -469,7 +763,8 @@ class User {
         return userObject.rewardTokenPending.toNumber();
     }
 
-    async claim(vault) {
+    // `streams` holds one { vault, account } pair per reward stream to pay, in stream order.
+    async claim(vault, streams = []) {
         let poolObject = await this.program.account.pool.fetch(this.poolPubkey);
 
         const [
@@ -492,6 +787,26 @@ class User {
             this.program.programId
         );
 
+        const [
+            rewardStreams,
+        ] = await anchor.web3.PublicKey.findProgramAddress(
+            [
+                this.poolPubkey.toBuffer(),
+                Buffer.from('reward_streams')
+            ],
+            this.program.programId
+        );
+
+        const [
+            setBonus,
+        ] = await anchor.web3.PublicKey.findProgramAddress(
+            [
+                this.poolPubkey.toBuffer(),
+                Buffer.from('set_bonus')
+            ],
+            this.program.programId
+        );
+
         await this.program.rpc.claim({
             accounts: {
                 // Stake instance.
@@ -502,6 +817,8 @@ class User {
                 user: this.userPubkey,
                 userStore: this.userStorePubkey,
                 cmRewardPerToken,
+                rewardStreams,
+                setBonus,
                 owner: this.provider.wallet.publicKey,
                 rewardAccount: this.mintRewardsPubkey,
                 // Program signers.
@@ -509,7 +826,12 @@ class User {
                 // Misc.
                 clock: anchor.web3.SYSVAR_CLOCK_PUBKEY,
                 tokenProgram: TOKEN_PROGRAM_ID,
+                systemProgram: anchor.web3.SystemProgram.programId,
             },
+            remainingAccounts: streams.flatMap(stream => [
+                { pubkey: stream.vault, isWritable: true, isSigner: false },
+                { pubkey: stream.account, isWritable: true, isSigner: false },
+            ]),
         });
 
         let amt = await this.provider.connection.getTokenAccountBalance(this.mintRewardsPubkey);
@@ -518,14 +840,7 @@ class User {
     }
 
     async setRewardPerToken(candyMachine, rewardPerToken) {
-        await this.program.rpc.setRewardPerToken(rewardPerToken, {
-            accounts: {
-                // Stake instance.
-                pool: this.poolPubkey,
-                authority: this.provider.wallet.publicKey,
-                systemProgram: anchor.web3.SystemProgram.programId,
-            },
-        });
+        return await this.queuePendingAction('queueSetRewardPerToken', rewardPerToken);
     }
 
     async createCandyMachineRewardPerToken() {
@@ -550,72 +865,189 @@ class User {
         });
     }
 
-    async setCandyMachineRewardPerToken(candyMachine, reward) {
+    async createRewardStreams() {
         const [
-            cmRewardPerToken,
+            rewardStreams,
             nonce,
         ] = await anchor.web3.PublicKey.findProgramAddress(
             [
                 this.poolPubkey.toBuffer(),
-                Buffer.from('reward_per_token')
+                Buffer.from('reward_streams')
             ],
             this.program.programId
         );
-        await this.program.rpc.setCandyMachineRewardPerToken(candyMachine, new anchor.BN(reward * anchor.web3.LAMPORTS_PER_SOL), {
+        await this.program.rpc.createRewardStreams(nonce, {
             accounts: {
                 // Stake instance.
                 pool: this.poolPubkey,
-                cmRewardPerToken: cmRewardPerToken,
+                rewardStreams,
                 authority: this.provider.wallet.publicKey,
                 systemProgram: anchor.web3.SystemProgram.programId,
             },
         });
     }
 
-    async removeCandyMachineRewardPerToken(candyMachine) {
+    // Adds a stream of `rewardMint` (a Token) paying from a new pool signer vault; returns the vault.
+    async addRewardStream(rewardMint, rewardPerToken, startTime, endTime) {
+        const [poolSigner] = await anchor.web3.PublicKey.findProgramAddress(
+            [this.poolPubkey.toBuffer()],
+            this.program.programId
+        );
+        const [rewardStreams] = await anchor.web3.PublicKey.findProgramAddress(
+            [
+                this.poolPubkey.toBuffer(),
+                Buffer.from('reward_streams')
+            ],
+            this.program.programId
+        );
+        let rewardVault = await rewardMint.createAccount(poolSigner);
+
+        await this.program.rpc.addRewardStream(
+            new anchor.BN(rewardPerToken),
+            new anchor.BN(startTime),
+            new anchor.BN(endTime),
+            {
+                accounts: {
+                    pool: this.poolPubkey,
+                    rewardStreams,
+                    rewardMint: rewardMint.publicKey,
+                    rewardVault,
+                    poolSigner,
+                    authority: this.provider.wallet.publicKey,
+                },
+            }
+        );
+        return rewardVault;
+    }
+
+    async createSetBonus() {
         const [
-            cmRewardPerToken,
+            setBonus,
             nonce,
         ] = await anchor.web3.PublicKey.findProgramAddress(
             [
                 this.poolPubkey.toBuffer(),
-                Buffer.from('reward_per_token')
+                Buffer.from('set_bonus')
             ],
             this.program.programId
         );
-        await this.program.rpc.removeCandyMachineRewardPerToken(candyMachine, {
+        await this.program.rpc.createSetBonus(nonce, {
             accounts: {
                 // Stake instance.
                 pool: this.poolPubkey,
-                cmRewardPerToken: cmRewardPerToken,
+                setBonus,
                 authority: this.provider.wallet.publicKey,
                 systemProgram: anchor.web3.SystemProgram.programId,
             },
         });
     }
 
-    async addCandyMachine(candyMachine, rewardType, isVerify, vault) {
+    async setCandyMachineRewardPerToken(candyMachine, reward) {
         const [
-            _poolSigner,
-            _nonce,
+            cmRewardPerToken,
+            nonce,
         ] = await anchor.web3.PublicKey.findProgramAddress(
-            [this.poolPubkey.toBuffer()],
+            [
+                this.poolPubkey.toBuffer(),
+                Buffer.from('reward_per_token')
+            ],
             this.program.programId
         );
-        let poolSigner = _poolSigner;
+        await this.runTimelocked('setCandyMachineRewardPerToken', [candyMachine, new anchor.BN(reward * anchor.web3.LAMPORTS_PER_SOL)], {
+            // Stake instance.
+            pool: this.poolPubkey,
+            vault: this.admin.vaultPubkey,
+            cmRewardPerToken: cmRewardPerToken,
+            authority: this.provider.wallet.publicKey,
+            systemProgram: anchor.web3.SystemProgram.programId,
+        });
+    }
 
-        await this.program.rpc.addCandyMachine(candyMachine, rewardType, isVerify, {
-            accounts: {
-                // Stake instance.
-                pool: this.poolPubkey,
-                vault,
-                authority: this.provider.wallet.publicKey,
-                systemProgram: anchor.web3.SystemProgram.programId,
-            },
+    async removeCandyMachineRewardPerToken(candyMachine) {
+        const [
+            cmRewardPerToken,
+            nonce,
+        ] = await anchor.web3.PublicKey.findProgramAddress(
+            [
+                this.poolPubkey.toBuffer(),
+                Buffer.from('reward_per_token')
+            ],
+            this.program.programId
+        );
+        await this.runTimelocked('removeCandyMachineRewardPerToken', [candyMachine], {
+            // Stake instance.
+            pool: this.poolPubkey,
+            vault: this.admin.vaultPubkey,
+            cmRewardPerToken: cmRewardPerToken,
+            authority: this.provider.wallet.publicKey,
+            systemProgram: anchor.web3.SystemProgram.programId,
         });
     }
 
-    async setVerifyCandyMachine(candyMachine, isVerify, vault) {
+    async setRarityRoot(rarityRoot) {
+        await this.runTimelocked('setRarityRoot', [rarityRoot], {
+            pool: this.poolPubkey,
+            authority: this.provider.wallet.publicKey,
+        });
+    }
+
+    async setEmissionSchedule(startTime, endTime, stepInterval, stepBps) {
+        const [
+            cmRewardPerToken,
+        ] = await anchor.web3.PublicKey.findProgramAddress(
+            [
+                this.poolPubkey.toBuffer(),
+                Buffer.from('reward_per_token')
+            ],
+            this.program.programId
+        );
+        const [
+            setBonus,
+        ] = await anchor.web3.PublicKey.findProgramAddress(
+            [
+                this.poolPubkey.toBuffer(),
+                Buffer.from('set_bonus')
+            ],
+            this.program.programId
+        );
+        await this.runTimelocked('setEmissionSchedule', [startTime, endTime, stepInterval, stepBps], {
+            // Stake instance.
+            pool: this.poolPubkey,
+            vault: this.admin.vaultPubkey,
+            cmRewardPerToken,
+            setBonus,
+            authority: this.provider.wallet.publicKey,
+        });
+    }
+
+    async setSetBonus(candyMachines, bonusBps) {
+        const [
+            setBonus,
+        ] = await anchor.web3.PublicKey.findProgramAddress(
+            [
+                this.poolPubkey.toBuffer(),
+                Buffer.from('set_bonus')
+            ],
+            this.program.programId
+        );
+        await this.runTimelocked('setSetBonus', [candyMachines, bonusBps], {
+            // Stake instance.
+            pool: this.poolPubkey,
+            setBonus,
+            authority: this.provider.wallet.publicKey,
+        });
+    }
+
+    async setEarlyUnstakePenalty(candyMachine, penaltyBps) {
+        await this.runTimelocked('setEarlyUnstakePenalty', [candyMachine, penaltyBps], {
+            // Stake instance.
+            pool: this.poolPubkey,
+            vault: this.admin.vaultPubkey,
+            authority: this.provider.wallet.publicKey,
+        });
+    }
+
+    async addCandyMachine(candyMachine, rewardType, vault) {
         const [
             _poolSigner,
             _nonce,
@@ -625,11 +1057,22 @@ class User {
         );
         let poolSigner = _poolSigner;
 
-        await this.program.rpc.setVerifyCandyMachine(candyMachine, isVerify, {
+        const [
+            cmRewardPerToken,
+        ] = await anchor.web3.PublicKey.findProgramAddress(
+            [
+                this.poolPubkey.toBuffer(),
+                Buffer.from('reward_per_token')
+            ],
+            this.program.programId
+        );
+
+        await this.program.rpc.addCandyMachine(candyMachine, rewardType, {
             accounts: {
                 // Stake instance.
                 pool: this.poolPubkey,
                 vault,
+                cmRewardPerToken,
                 authority: this.provider.wallet.publicKey,
                 systemProgram: anchor.web3.SystemProgram.programId,
             },
@@ -646,13 +1089,48 @@ class User {
         );
         let poolSigner = _poolSigner;
 
-        await this.program.rpc.removeCandyMachine(candyMachine, {
+        const [
+            cmRewardPerToken,
+        ] = await anchor.web3.PublicKey.findProgramAddress(
+            [
+                this.poolPubkey.toBuffer(),
+                Buffer.from('reward_per_token')
+            ],
+            this.program.programId
+        );
+
+        await this.runTimelocked('removeCandyMachine', [candyMachine], {
+            // Stake instance.
+            pool: this.poolPubkey,
+            vault,
+            cmRewardPerToken,
+            authority: this.provider.wallet.publicKey,
+        });
+    }
+
+    async proposeAuthority(newAuthority) {
+        await this.program.rpc.proposeAuthority(newAuthority, {
             accounts: {
-                // Stake instance.
                 pool: this.poolPubkey,
-                vault,
                 authority: this.provider.wallet.publicKey,
-                systemProgram: anchor.web3.SystemProgram.programId,
+            },
+        });
+    }
+
+    async setRoles(rateManager, collectionManager, pauser, treasurer) {
+        await this.program.rpc.setRoles(rateManager, collectionManager, pauser, treasurer, {
+            accounts: {
+                pool: this.poolPubkey,
+                authority: this.provider.wallet.publicKey,
+            },
+        });
+    }
+
+    async acceptAuthority() {
+        await this.program.rpc.acceptAuthority({
+            accounts: {
+                pool: this.poolPubkey,
+                pendingAuthority: this.provider.wallet.publicKey,
             },
         });
     }
@@ -670,6 +1148,19 @@ class User {
             });
     }
 
+    async closeUserStore(userStorePubkey = this.userStorePubkey) {
+        await this.program.rpc.closeUserStore(
+            {
+                accounts: {
+                    // Stake instance.
+                    pool: this.poolPubkey,
+                    user: this.userPubkey,
+                    userStore: userStorePubkey,
+                    owner: this.provider.wallet.publicKey,
+                },
+            });
+    }
+
     async createNFT() {
         //create new token mint
         let mint = await Token.createMint(
@@ -677,7 +1168,7 @@ class User {
           this.keypair,
           this.pubkey,
           null,
-          9,
+          0,
           TOKEN_PROGRAM_ID,
         );
 
@@ -689,7 +1180,7 @@ class User {
           nftAccount.address,
           this.pubkey,
           [],
-          1000000000,
+          1,
         );
 
         await mint.setAuthority(
@@ -707,5 +1198,6 @@ class User {
 
 module.exports = {
     claimForUsers,
+    getMetadata,
     User
 };